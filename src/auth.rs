@@ -0,0 +1,338 @@
+use regex::Regex;
+use reqwest::{Method, Url};
+use scraper::{Html, Selector};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use tracing::info;
+
+use crate::fetch::Fetcher;
+
+/// Credentials sent with every request to a host.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum HostAuth {
+    Basic {
+        username: String,
+        password: Option<String>,
+    },
+    Bearer {
+        token: String,
+    },
+}
+
+impl HostAuth {
+    pub fn apply(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match self {
+            HostAuth::Basic { username, password } => {
+                request.basic_auth(username, password.as_ref())
+            }
+            HostAuth::Bearer { token } => request.bearer_auth(token),
+        }
+    }
+}
+
+/// A scripted form login, run before every crawl or scrape so the session
+/// cookies it receives are shared by everything that follows.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct LoginFlow {
+    // The page with the login form
+    pub url: String,
+    // Selects the form; defaults to the first one with a password field
+    pub form: Option<String>,
+    // Values for named form fields, such as the username and password
+    #[serde(default)]
+    pub fields: BTreeMap<String, String>,
+    // Fields whose value is read from the login page by CSS selector, such as
+    // CSRF tokens outside the form: the element's value or content attribute
+    #[serde(default)]
+    pub tokens: BTreeMap<String, String>,
+    // Logging in succeeded if the resulting page matches this selector...
+    pub success_selector: Option<String>,
+    // ...and its final URL matches this regex
+    pub success_url: Option<String>,
+}
+
+impl LoginFlow {
+    /// Fetches the login page, fills in and submits its form, and checks the
+    /// resulting page for success.
+    pub async fn run(&self, fetcher: &Fetcher) -> Result<(), Box<dyn std::error::Error>> {
+        info!(url = %self.url, "Logging in");
+        let page = fetcher.fetch(&self.url).await?;
+        if !page.is_success() {
+            return Err(format!("Login page {} returned HTTP {}", self.url, page.status).into());
+        }
+
+        let form = self.fill_form(&page.body, &page.final_url)?;
+        let response = fetcher
+            .submit_form(form.method, &form.action, &form.fields)
+            .await?;
+
+        let mut failures = Vec::new();
+        if let Some(selector) = &self.success_selector {
+            let selector = parse_selector(selector)?;
+            if Html::parse_document(&response.body)
+                .select(&selector)
+                .next()
+                .is_none()
+            {
+                failures.push("the success selector matched nothing".to_string());
+            }
+        }
+        if let Some(pattern) = &self.success_url {
+            let pattern = Regex::new(pattern)
+                .map_err(|err| format!("Invalid success_url regex '{}': {}", pattern, err))?;
+            if !pattern.is_match(&response.final_url) {
+                failures.push(format!("ended up on {}", response.final_url));
+            }
+        }
+        if self.success_selector.is_none() && self.success_url.is_none() && !response.is_success() {
+            failures.push(format!("HTTP {}", response.status));
+        }
+
+        if failures.is_empty() {
+            info!(url = %response.final_url, "Logged in");
+            Ok(())
+        } else {
+            Err(format!("Login at {} failed: {}", self.url, failures.join(", ")).into())
+        }
+    }
+
+    /// The form's method, its action resolved against the page, and its
+    /// fields: the form's own inputs (hidden ones included), then the
+    /// configured tokens and fields on top.
+    fn fill_form(&self, html: &str, page_url: &str) -> Result<FilledForm, String> {
+        let document = Html::parse_document(html);
+        let forms = parse_selector(self.form.as_deref().unwrap_or("form"))?;
+        let password = Selector::parse("input[type=password]").unwrap();
+        let mut candidates = document.select(&forms);
+        let form = match self.form {
+            Some(_) => candidates.next(),
+            None => candidates
+                .clone()
+                .find(|form| form.select(&password).next().is_some())
+                .or_else(|| candidates.next()),
+        }
+        .ok_or_else(|| format!("No login form found on {}", page_url))?;
+
+        let mut fields: Vec<(String, String)> = Vec::new();
+        let inputs = Selector::parse("input[name], textarea[name], select[name]").unwrap();
+        for input in form.select(&inputs) {
+            let element = input.value();
+            let kind = element.attr("type").unwrap_or("text").to_ascii_lowercase();
+            let value = match element.name() {
+                "textarea" => input.text().collect(),
+                "select" => {
+                    let selected = Selector::parse("option[selected]").unwrap();
+                    let option = Selector::parse("option").unwrap();
+                    match input
+                        .select(&selected)
+                        .next()
+                        .or_else(|| input.select(&option).next())
+                    {
+                        Some(option) => option
+                            .value()
+                            .attr("value")
+                            .map(str::to_string)
+                            .unwrap_or_else(|| option.text().collect()),
+                        None => continue,
+                    }
+                }
+                _ if matches!(
+                    kind.as_str(),
+                    "submit" | "button" | "image" | "reset" | "file"
+                ) =>
+                {
+                    continue
+                }
+                _ if matches!(kind.as_str(), "checkbox" | "radio")
+                    && element.attr("checked").is_none() =>
+                {
+                    continue
+                }
+                _ => element.attr("value").unwrap_or_default().to_string(),
+            };
+            fields.push((element.attr("name").unwrap().to_string(), value));
+        }
+
+        for (name, selector) in &self.tokens {
+            let selector = parse_selector(selector)?;
+            let element = document.select(&selector).next().ok_or_else(|| {
+                format!("Token '{}' not found on {} by its selector", name, page_url)
+            })?;
+            let value = element
+                .value()
+                .attr("value")
+                .or_else(|| element.value().attr("content"))
+                .map(str::to_string)
+                .unwrap_or_else(|| element.text().collect::<String>().trim().to_string());
+            set_field(&mut fields, name, value);
+        }
+        for (name, value) in &self.fields {
+            set_field(&mut fields, name, value.clone());
+        }
+
+        let page_url = Url::parse(page_url).map_err(|err| err.to_string())?;
+        let action = match form.value().attr("action").map(str::trim) {
+            Some(action) if !action.is_empty() => page_url
+                .join(action)
+                .map_err(|err| format!("Invalid login form action '{}': {}", action, err))?,
+            _ => page_url,
+        };
+        let method = match form.value().attr("method") {
+            Some(method) if method.eq_ignore_ascii_case("post") => Method::POST,
+            _ => Method::GET,
+        };
+        Ok(FilledForm {
+            method,
+            action: action.to_string(),
+            fields,
+        })
+    }
+}
+
+struct FilledForm {
+    method: Method,
+    action: String,
+    fields: Vec<(String, String)>,
+}
+
+fn set_field(fields: &mut Vec<(String, String)>, name: &str, value: String) {
+    match fields.iter_mut().find(|(field, _)| field == name) {
+        Some((_, existing)) => *existing = value,
+        None => fields.push((name.to_string(), value)),
+    }
+}
+
+fn parse_selector(selector: &str) -> Result<Selector, String> {
+    Selector::parse(selector).map_err(|err| format!("Invalid selector '{}': {}", selector, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use serde_json::json;
+
+    fn authorization(auth: &HostAuth) -> String {
+        let request = auth
+            .apply(reqwest::Client::new().get("https://example.com/"))
+            .build()
+            .unwrap();
+        request.headers()["authorization"]
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    fn bearer(token: &str) -> HostAuth {
+        HostAuth::Bearer {
+            token: token.to_string(),
+        }
+    }
+
+    fn login(flow: serde_json::Value) -> LoginFlow {
+        serde_json::from_value(flow).unwrap()
+    }
+
+    #[test]
+    fn host_auth_sets_the_authorization_header() {
+        let basic: HostAuth = serde_json::from_value(
+            json!({"type": "basic", "username": "user", "password": "pass"}),
+        )
+        .unwrap();
+        assert_eq!(authorization(&basic), "Basic dXNlcjpwYXNz");
+        let no_password: HostAuth =
+            serde_json::from_value(json!({"type": "basic", "username": "user"})).unwrap();
+        assert_eq!(authorization(&no_password), "Basic dXNlcjo=");
+        assert_eq!(authorization(&bearer("t0ken")), "Bearer t0ken");
+    }
+
+    #[test]
+    fn host_auth_is_picked_by_host() {
+        let hosts = vec![
+            ("*.example.com".to_string(), bearer("wildcard")),
+            ("api.example.com".to_string(), bearer("host")),
+            ("api.example.com:8443".to_string(), bearer("port")),
+        ];
+        let token = |url: &str| match config::for_host(&hosts, url) {
+            Some((_, HostAuth::Bearer { token })) => Some(token.as_str()),
+            _ => None,
+        };
+
+        assert_eq!(token("https://API.example.com/v1"), Some("host"));
+        assert_eq!(token("https://api.example.com:8443/v1"), Some("port"));
+        assert_eq!(token("https://www.example.com/"), Some("wildcard"));
+        assert_eq!(token("https://example.com/"), None);
+        assert_eq!(token("https://example.org/"), None);
+    }
+
+    #[test]
+    fn fills_the_form_with_a_password_field() {
+        let html = r#"<meta name="csrf" content="meta-token">
+            <form action="/search"><input name="q"></form>
+            <form action="session" method="POST">
+              <input type="hidden" name="csrf" value="form-token">
+              <input name="username" value="placeholder">
+              <input type="password" name="password">
+              <input type="checkbox" name="remember" value="yes" checked>
+              <input type="checkbox" name="newsletter" value="yes">
+              <select name="lang"><option value="en">English</option>
+                <option value="de" selected>Deutsch</option></select>
+              <select name="region"><option>EU</option><option>US</option></select>
+              <textarea name="note">hi</textarea>
+              <input type="submit" name="go" value="Log in">
+            </form>"#;
+        let flow = login(json!({
+            "url": "https://example.com/account/login",
+            "fields": {"username": "alice", "password": "secret"},
+            "tokens": {"x-csrf": "meta[name=csrf]"},
+        }));
+
+        let form = flow
+            .fill_form(html, "https://example.com/account/login")
+            .unwrap();
+        assert_eq!(form.method, Method::POST);
+        assert_eq!(form.action, "https://example.com/account/session");
+        let fields: Vec<(&str, &str)> = form
+            .fields
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                ("csrf", "form-token"),
+                ("username", "alice"),
+                ("password", "secret"),
+                ("remember", "yes"),
+                ("lang", "de"),
+                ("region", "EU"),
+                ("note", "hi"),
+                ("x-csrf", "meta-token"),
+            ]
+        );
+    }
+
+    #[test]
+    fn fills_the_selected_form_and_reports_missing_ones() {
+        let html = r#"<form id="first"><input type="password" name="pin"></form>
+            <form id="second"><input name="user"></form>"#;
+        let flow = login(json!({"url": "https://example.com/login", "form": "#second"}));
+
+        let form = flow
+            .fill_form(html, "https://example.com/login?next=/")
+            .unwrap();
+        // No method or action: a GET back to the page itself
+        assert_eq!(form.method, Method::GET);
+        assert_eq!(form.action, "https://example.com/login?next=/");
+        assert_eq!(form.fields, [("user".to_string(), String::new())]);
+
+        let flow = login(json!({"url": "https://example.com/login", "form": "#third"}));
+        let err = flow.fill_form(html, "https://example.com/login").err();
+        assert_eq!(
+            err.as_deref(),
+            Some("No login form found on https://example.com/login")
+        );
+    }
+}
@@ -0,0 +1,157 @@
+use chardetng::{EncodingDetector, Iso2022JpDetection, Utf8Detection};
+use encoding_rs::Encoding;
+use regex::bytes::Regex;
+use std::sync::LazyLock;
+
+/// How many bytes are scanned for a `<meta charset>` or XML declaration,
+/// the same limit browsers use for their prescan.
+const PRESCAN_LENGTH: usize = 1024;
+
+/// `<meta charset="...">`, `<meta http-equiv="Content-Type" content="...; charset=...">`
+/// or `<?xml ... encoding="..."?>`.
+static META_CHARSET: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r#"(?i)<meta[^>]+charset\s*=\s*["']?([\w.:-]+)|<\?xml[^>]+encoding\s*=\s*["']([\w.:-]+)"#,
+    )
+    .unwrap()
+});
+
+/// Where a page's encoding was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Bom,
+    Header,
+    Meta,
+    Detected,
+}
+
+impl Source {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Source::Bom => "bom",
+            Source::Header => "header",
+            Source::Meta => "meta",
+            Source::Detected => "detected",
+        }
+    }
+}
+
+/// Decodes a response body, looking for its encoding in a byte order mark,
+/// the Content-Type charset, a `<meta charset>` tag or XML declaration, and
+/// finally guessing from the bytes themselves.
+pub fn decode(body: &[u8], content_type: Option<&str>) -> (String, &'static Encoding, Source) {
+    let (encoding, source) = detect(body, content_type);
+    // decode() strips a BOM matching the encoding and replaces invalid sequences
+    let (text, _, _) = encoding.decode(body);
+    (text.into_owned(), encoding, source)
+}
+
+fn detect(body: &[u8], content_type: Option<&str>) -> (&'static Encoding, Source) {
+    if let Some((encoding, _)) = Encoding::for_bom(body) {
+        return (encoding, Source::Bom);
+    }
+    if let Some(encoding) = content_type.and_then(header_charset) {
+        return (encoding, Source::Header);
+    }
+    if let Some(encoding) = meta_charset(&body[..body.len().min(PRESCAN_LENGTH)]) {
+        return (encoding, Source::Meta);
+    }
+
+    let mut detector = EncodingDetector::new(Iso2022JpDetection::Deny);
+    detector.feed(body, true);
+    (detector.guess(None, Utf8Detection::Allow), Source::Detected)
+}
+
+fn header_charset(content_type: &str) -> Option<&'static Encoding> {
+    content_type
+        .split(';')
+        .skip(1)
+        .filter_map(|parameter| parameter.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+        .and_then(|(_, value)| Encoding::for_label(value.trim().trim_matches('"').as_bytes()))
+}
+
+/// The encoding a `<meta>` tag or XML declaration in `head` names.
+fn meta_charset(head: &[u8]) -> Option<&'static Encoding> {
+    let captures = META_CHARSET.captures(head)?;
+    let label = captures.get(1).or_else(|| captures.get(2))?;
+    let encoding = Encoding::for_label(label.as_bytes())?;
+
+    // A document that could be read to find this tag is ASCII-compatible, so a
+    // UTF-16 label here is wrong; browsers treat it as UTF-8
+    if encoding == encoding_rs::UTF_16LE || encoding == encoding_rs::UTF_16BE {
+        return Some(encoding_rs::UTF_8);
+    }
+    Some(encoding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect_name(body: &[u8], content_type: Option<&str>) -> (&'static str, Source) {
+        let (encoding, source) = detect(body, content_type);
+        (encoding.name(), source)
+    }
+
+    #[test]
+    fn bom_wins_over_everything_else() {
+        let body = b"\xEF\xBB\xBF<meta charset=\"iso-8859-1\">caf\xC3\xA9";
+        assert_eq!(
+            detect_name(body, Some("text/html; charset=windows-1252")),
+            ("UTF-8", Source::Bom)
+        );
+        let (text, _, _) = decode(body, None);
+        assert_eq!(text, "<meta charset=\"iso-8859-1\">café");
+    }
+
+    #[test]
+    fn header_charset_comes_before_meta() {
+        assert_eq!(
+            detect_name(
+                b"<meta charset=utf-8>",
+                Some("text/html; Charset=\"Shift_JIS\"")
+            ),
+            ("Shift_JIS", Source::Header)
+        );
+        // Unknown labels are skipped
+        assert_eq!(
+            detect_name(b"<meta charset=utf-8>", Some("text/html; charset=bogus")),
+            ("UTF-8", Source::Meta)
+        );
+    }
+
+    #[test]
+    fn meta_tags_and_xml_declarations() {
+        let http_equiv =
+            br#"<meta http-equiv="Content-Type" content="text/html; charset=windows-1251">"#;
+        assert_eq!(
+            detect_name(http_equiv, Some("text/html")),
+            ("windows-1251", Source::Meta)
+        );
+        assert_eq!(
+            detect_name(br#"<?xml version="1.0" encoding='ISO-8859-2'?>"#, None),
+            ("ISO-8859-2", Source::Meta)
+        );
+        // A UTF-16 label in an ASCII-readable document means UTF-8
+        assert_eq!(
+            detect_name(br#"<meta charset="utf-16">"#, None),
+            ("UTF-8", Source::Meta)
+        );
+    }
+
+    #[test]
+    fn meta_past_the_prescan_is_ignored() {
+        let mut body = vec![b' '; PRESCAN_LENGTH];
+        body.extend_from_slice(b"<meta charset=\"windows-1251\">");
+        assert_eq!(detect_name(&body, None).1, Source::Detected);
+    }
+
+    #[test]
+    fn guesses_from_the_bytes() {
+        let (text, encoding, source) = decode(b"caf\xE9 cr\xE8me br\xFBl\xE9e", None);
+        assert_eq!(source, Source::Detected);
+        assert_eq!(encoding, encoding_rs::WINDOWS_1252);
+        assert_eq!(text, "café crème brûlée");
+    }
+}
@@ -0,0 +1,119 @@
+use indicatif::ProgressBar;
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    sync::Mutex,
+};
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::{
+    fmt::{self, MakeWriter},
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
+    EnvFilter, Layer,
+};
+
+// The live progress display, which log lines on stderr have to go around
+static PROGRESS_BAR: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+pub fn set_progress_bar(bar: Option<ProgressBar>) {
+    *PROGRESS_BAR.lock().unwrap() = bar;
+}
+
+/// Writes to stderr, hiding the progress display while a log line is written.
+struct Stderr;
+
+impl Write for Stderr {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let bar = PROGRESS_BAR.lock().unwrap().clone();
+        match bar {
+            Some(bar) => bar.suspend(|| io::stderr().write(buf)),
+            None => io::stderr().write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+impl<'a> MakeWriter<'a> for Stderr {
+    type Writer = Stderr;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        Stderr
+    }
+}
+
+/// How much is logged, in what format, and where.
+#[derive(Clone, Debug)]
+pub struct LogSettings {
+    // Positive for each -v, negative for each -q
+    pub verbosity: i8,
+    pub json: bool,
+    // Also append log lines here
+    pub file: Option<String>,
+}
+
+impl LogSettings {
+    fn level(&self) -> LevelFilter {
+        match self.verbosity {
+            i8::MIN..=-2 => LevelFilter::ERROR,
+            -1 => LevelFilter::WARN,
+            0 => LevelFilter::INFO,
+            1 => LevelFilter::DEBUG,
+            _ => LevelFilter::TRACE,
+        }
+    }
+}
+
+/// Sends log events to stderr, keeping stdout for data, and to the log file
+/// if one is set. `RUST_LOG` overrides the verbosity flags; otherwise other
+/// crates only log warnings and errors.
+pub fn init_logging(settings: &LogSettings) -> Result<(), String> {
+    let level = settings.level();
+    let filter = match std::env::var("RUST_LOG") {
+        Ok(directives) if !directives.is_empty() => EnvFilter::new(directives),
+        _ => EnvFilter::new(format!(
+            "{},{}={}",
+            level.min(LevelFilter::WARN),
+            env!("CARGO_CRATE_NAME"),
+            level
+        )),
+    };
+
+    let file = match &settings.file {
+        Some(path) => Some(
+            OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(path)
+                .map_err(|err| format!("Error opening log file {}: {}", path, err))?,
+        ),
+        None => None,
+    };
+
+    let console = fmt::layer().with_writer(Stderr).with_target(false);
+    let console = if settings.json {
+        console.json().with_span_list(true).boxed()
+    } else {
+        console.boxed()
+    };
+    let file = file.map(|file| {
+        let layer = fmt::layer()
+            .with_writer(Mutex::new(file))
+            .with_ansi(false)
+            .with_target(false);
+        if settings.json {
+            layer.json().with_span_list(true).boxed()
+        } else {
+            layer.boxed()
+        }
+    });
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(console)
+        .with(file)
+        .try_init()
+        .map_err(|err| format!("Could not set up logging: {}", err))
+}
@@ -0,0 +1,281 @@
+/// MIME types that are sniffed from the first bytes of a body. Binary
+/// signatures win over the Content-Type header, which servers often get wrong.
+const SIGNATURES: &[(&[u8], &str)] = &[
+    (b"%PDF-", "application/pdf"),
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"%!PS", "application/postscript"),
+    (b"\x00\x00\x01\x00", "image/x-icon"),
+    (b"OggS", "audio/ogg"),
+    (b"ID3", "audio/mpeg"),
+    (b"wOFF", "font/woff"),
+    (b"wOF2", "font/woff2"),
+];
+
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("text/html", "html"),
+    ("application/xhtml+xml", "html"),
+    ("text/plain", "txt"),
+    ("text/css", "css"),
+    ("text/csv", "csv"),
+    ("text/javascript", "js"),
+    ("application/javascript", "js"),
+    ("application/json", "json"),
+    ("application/xml", "xml"),
+    ("text/xml", "xml"),
+    ("application/rss+xml", "xml"),
+    ("application/atom+xml", "xml"),
+    ("application/pdf", "pdf"),
+    ("image/png", "png"),
+    ("image/jpeg", "jpg"),
+    ("image/gif", "gif"),
+    ("image/webp", "webp"),
+    ("image/svg+xml", "svg"),
+    ("image/x-icon", "ico"),
+    ("application/zip", "zip"),
+    ("application/gzip", "gz"),
+    ("application/postscript", "ps"),
+    ("audio/ogg", "ogg"),
+    ("audio/mpeg", "mp3"),
+    ("font/woff", "woff"),
+    ("font/woff2", "woff2"),
+];
+
+/// The MIME type from a Content-Type header value, without parameters.
+pub fn header_mime(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
+/// The MIME type of a response body: a recognised binary signature, else the
+/// Content-Type header, else a guess from what the text looks like.
+pub fn detect_mime(content_type: Option<&str>, body: &[u8]) -> String {
+    if let Some(mime) = sniff_binary(body) {
+        return mime.to_string();
+    }
+
+    let declared = content_type.map(header_mime).unwrap_or_default();
+    if !declared.is_empty() && declared != "application/octet-stream" {
+        return declared;
+    }
+    sniff_text(body)
+        .unwrap_or("application/octet-stream")
+        .to_string()
+}
+
+fn sniff_binary(body: &[u8]) -> Option<&'static str> {
+    if body.len() >= 12 && &body[..4] == b"RIFF" && &body[8..12] == b"WEBP" {
+        return Some("image/webp");
+    }
+    SIGNATURES
+        .iter()
+        .find(|(signature, _)| body.starts_with(signature))
+        .map(|(_, mime)| *mime)
+}
+
+fn sniff_text(body: &[u8]) -> Option<&'static str> {
+    let head = String::from_utf8_lossy(&body[..body.len().min(512)]).to_ascii_lowercase();
+    let head = head.trim_start_matches('\u{feff}').trim_start();
+
+    if head.starts_with("<!doctype html") || head.starts_with("<html") {
+        Some("text/html")
+    } else if head.starts_with("<?xml") {
+        if head.contains("<svg") {
+            Some("image/svg+xml")
+        } else {
+            Some("application/xml")
+        }
+    } else if head.starts_with('{') || head.starts_with('[') {
+        Some("application/json")
+    } else if !head.is_empty() && !body.iter().take(512).any(|&byte| byte == 0) {
+        Some("text/plain")
+    } else {
+        None
+    }
+}
+
+pub fn is_html(mime: &str) -> bool {
+    matches!(mime, "text/html" | "application/xhtml+xml")
+}
+
+/// Whether the body is text the extractors can work on (HTML, XML, JSON, plain text).
+pub fn is_text(mime: &str) -> bool {
+    mime.starts_with("text/")
+        || mime.ends_with("+xml")
+        || mime.ends_with("+json")
+        || matches!(
+            mime,
+            "application/json" | "application/xml" | "application/javascript"
+        )
+}
+
+/// File extension for saving a body of this MIME type, if it is a known one.
+pub fn extension_for(mime: &str) -> Option<&'static str> {
+    EXTENSIONS
+        .iter()
+        .find(|(known, _)| *known == mime)
+        .map(|(_, extension)| *extension)
+}
+
+/// Which responses to fetch at all, and how large each kind may be.
+#[derive(Clone, Debug, Default)]
+pub struct ContentPolicy {
+    allow: Vec<String>,
+    deny: Vec<String>,
+    max_sizes: Vec<(String, u64)>,
+    // Applies to every type, on top of the per-type limits
+    max_body_size: Option<u64>,
+}
+
+impl ContentPolicy {
+    /// Builds a policy from MIME patterns (`text/html`, `image/*`, `*`),
+    /// size limits written as `pattern=size` (e.g. `image/*=5MB`) and an
+    /// overall body size limit.
+    pub fn parse(
+        allow: &[String],
+        deny: &[String],
+        max_sizes: &[String],
+        max_body_size: Option<&str>,
+    ) -> Result<ContentPolicy, String> {
+        let max_sizes = max_sizes
+            .iter()
+            .map(|spec| {
+                let (pattern, size) = spec
+                    .split_once('=')
+                    .ok_or_else(|| format!("Invalid size limit '{}': expected type=size", spec))?;
+                Ok((pattern.trim().to_ascii_lowercase(), parse_size(size)?))
+            })
+            .collect::<Result<_, String>>()?;
+
+        Ok(ContentPolicy {
+            allow: allow
+                .iter()
+                .map(|mime| mime.trim().to_ascii_lowercase())
+                .collect(),
+            deny: deny
+                .iter()
+                .map(|mime| mime.trim().to_ascii_lowercase())
+                .collect(),
+            max_sizes,
+            max_body_size: max_body_size.map(parse_size).transpose()?,
+        })
+    }
+
+    /// Denied types always lose; with an allow list, only its types get through.
+    pub fn allows(&self, mime: &str) -> bool {
+        if self.deny.iter().any(|pattern| mime_matches(pattern, mime)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|pattern| mime_matches(pattern, mime))
+    }
+
+    /// The size limit of the most specific matching pattern, capped by the
+    /// overall body size limit.
+    pub fn max_size(&self, mime: &str) -> Option<u64> {
+        let type_limit = self
+            .max_sizes
+            .iter()
+            .filter(|(pattern, _)| mime_matches(pattern, mime))
+            .max_by_key(|(pattern, _)| specificity(pattern))
+            .map(|(_, size)| *size);
+
+        match (type_limit, self.max_body_size) {
+            (Some(type_limit), Some(max_body_size)) => Some(type_limit.min(max_body_size)),
+            (type_limit, max_body_size) => type_limit.or(max_body_size),
+        }
+    }
+}
+
+fn mime_matches(pattern: &str, mime: &str) -> bool {
+    match pattern.strip_suffix("/*") {
+        _ if pattern == "*" || pattern == "*/*" => true,
+        Some(kind) => mime.split('/').next() == Some(kind),
+        None => pattern == mime,
+    }
+}
+
+fn specificity(pattern: &str) -> u8 {
+    if pattern == "*" || pattern == "*/*" {
+        0
+    } else if pattern.ends_with("/*") {
+        1
+    } else {
+        2
+    }
+}
+
+/// Parses sizes such as `500000`, `512KB`, `10MB` or `1GB` into bytes.
+fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim().to_ascii_uppercase();
+    let (number, multiplier) = [("GB", 1 << 30), ("MB", 1 << 20), ("KB", 1 << 10), ("B", 1)]
+        .iter()
+        .find_map(|(suffix, multiplier)| Some((size.strip_suffix(suffix)?, *multiplier)))
+        .unwrap_or((size.as_str(), 1));
+
+    number
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .ok_or_else(|| format!("Invalid size '{}'", size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_size_reads_units() {
+        assert_eq!(parse_size("500000"), Ok(500_000));
+        assert_eq!(parse_size(" 512kb "), Ok(512 << 10));
+        assert_eq!(parse_size("10 MB"), Ok(10 << 20));
+        assert_eq!(parse_size("1GB"), Ok(1 << 30));
+        assert_eq!(parse_size("7B"), Ok(7));
+    }
+
+    #[test]
+    fn parse_size_rejects_bad_and_overflowing_sizes() {
+        assert_eq!(
+            parse_size("ten MB"),
+            Err("Invalid size 'TEN MB'".to_string())
+        );
+        assert!(parse_size("").is_err());
+        assert!(parse_size("-5KB").is_err());
+        assert!(parse_size("18446744073709551615").is_ok());
+        assert_eq!(
+            parse_size("18446744073709551615GB"),
+            Err("Invalid size '18446744073709551615GB'".to_string())
+        );
+        assert!(parse_size("17179869184GB").is_err());
+    }
+
+    #[test]
+    fn policy_picks_the_most_specific_limit() {
+        let policy = ContentPolicy::parse(
+            &[],
+            &["image/svg+xml".to_string()],
+            &[
+                "*=50MB".to_string(),
+                "image/*=5MB".to_string(),
+                "image/png=1MB".to_string(),
+            ],
+            Some("10MB"),
+        )
+        .unwrap();
+
+        assert_eq!(policy.max_size("image/png"), Some(1 << 20));
+        assert_eq!(policy.max_size("image/jpeg"), Some(5 << 20));
+        assert_eq!(policy.max_size("text/html"), Some(10 << 20));
+        assert!(policy.allows("image/png"));
+        assert!(!policy.allows("image/svg+xml"));
+        assert!(ContentPolicy::parse(&[], &[], &["image/*".to_string()], None).is_err());
+    }
+}
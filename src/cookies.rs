@@ -0,0 +1,368 @@
+use chrono::prelude::*;
+use cookie_store::{CookieDomain, CookieExpiration, CookieStore};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::{fs, io, path::Path};
+
+/// One cookie as exported by browser extensions such as Cookie-Editor, which
+/// is also the format `.json` jars are saved in.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct JsonCookie {
+    domain: String,
+    // Only sent to `domain` itself, not its subdomains
+    #[serde(default)]
+    host_only: bool,
+    #[serde(default = "root_path")]
+    path: String,
+    #[serde(default)]
+    secure: bool,
+    #[serde(default)]
+    http_only: bool,
+    // Seconds since the epoch; missing for session cookies
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expiration_date: Option<f64>,
+    name: String,
+    value: String,
+}
+
+fn root_path() -> String {
+    "/".to_string()
+}
+
+/// Whether a jar file is JSON rather than Netscape `cookies.txt`.
+fn is_json(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"))
+}
+
+/// Loads a cookie jar from a Netscape `cookies.txt` file, or from JSON when
+/// the file name ends in `.json`. Expired cookies are dropped.
+pub fn load(path: &str) -> Result<CookieStore, String> {
+    let content =
+        fs::read_to_string(path).map_err(|err| format!("Error reading {}: {}", path, err))?;
+    let cookies = if is_json(path) {
+        serde_json::from_str::<Vec<JsonCookie>>(&content)
+            .map_err(|err| format!("Invalid cookie file {}: {}", path, err))?
+    } else {
+        content
+            .lines()
+            .enumerate()
+            .filter_map(|(number, line)| {
+                parse_netscape_line(line)
+                    .map_err(|err| {
+                        format!("Invalid cookie on line {} of {}: {}", number + 1, path, err)
+                    })
+                    .transpose()
+            })
+            .collect::<Result<_, _>>()?
+    };
+
+    let mut store = CookieStore::new();
+    for cookie in cookies {
+        insert(&mut store, &cookie)
+            .map_err(|err| format!("Invalid cookie '{}' in {}: {}", cookie.name, path, err))?;
+    }
+    Ok(store)
+}
+
+/// Saves every unexpired cookie, session cookies included, in the format
+/// the file name calls for.
+pub fn save(store: &CookieStore, path: &str) -> io::Result<()> {
+    let cookies: Vec<JsonCookie> = store
+        .iter_unexpired()
+        .filter_map(|cookie| {
+            let (domain, host_only) = match &cookie.domain {
+                CookieDomain::HostOnly(domain) => (domain.clone(), true),
+                CookieDomain::Suffix(domain) => (domain.clone(), false),
+                _ => return None,
+            };
+            let expiration_date = match &cookie.expires {
+                CookieExpiration::AtUtc(at) => Some(at.unix_timestamp() as f64),
+                CookieExpiration::SessionEnd => None,
+            };
+            Some(JsonCookie {
+                domain,
+                host_only,
+                path: cookie.path.to_string(),
+                secure: cookie.secure().unwrap_or(false),
+                http_only: cookie.http_only().unwrap_or(false),
+                expiration_date,
+                name: cookie.name().to_string(),
+                value: cookie.value().to_string(),
+            })
+        })
+        .collect();
+
+    if let Some(folder) = Path::new(path).parent() {
+        fs::create_dir_all(folder)?;
+    }
+    if is_json(path) {
+        serde_json::to_writer_pretty(fs::File::create(path)?, &cookies)?;
+        return Ok(());
+    }
+
+    let mut content = String::from("# Netscape HTTP Cookie File\n");
+    for cookie in cookies {
+        let flag = |value: bool| if value { "TRUE" } else { "FALSE" };
+        // Browsers and curl mark domain cookies with a leading dot
+        let domain = if cookie.host_only {
+            cookie.domain
+        } else {
+            format!(".{}", cookie.domain)
+        };
+        content.push_str(&format!(
+            "{}{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+            if cookie.http_only { "#HttpOnly_" } else { "" },
+            domain,
+            flag(!cookie.host_only),
+            cookie.path,
+            flag(cookie.secure),
+            cookie.expiration_date.unwrap_or(0.0) as i64,
+            cookie.name,
+            cookie.value
+        ));
+    }
+    fs::write(path, content)
+}
+
+/// Parses a `cookies.txt` line: domain, include-subdomains flag, path,
+/// secure flag, expiry (0 for session cookies), name and value, separated
+/// by tabs. Comments and blank lines give `None`.
+fn parse_netscape_line(line: &str) -> Result<Option<JsonCookie>, String> {
+    let (line, http_only) = match line.strip_prefix("#HttpOnly_") {
+        Some(line) => (line, true),
+        None => (line, false),
+    };
+    if line.trim().is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    let fields: Vec<&str> = line.trim_end_matches(['\r', '\n']).split('\t').collect();
+    let [domain, include_subdomains, path, secure, expires, name, value] = fields[..] else {
+        return Err(format!(
+            "expected 7 tab-separated fields, found {}",
+            fields.len()
+        ));
+    };
+    let expires: i64 = expires
+        .parse()
+        .map_err(|_| format!("invalid expiry '{}'", expires))?;
+
+    Ok(Some(JsonCookie {
+        domain: domain.to_string(),
+        host_only: !include_subdomains.eq_ignore_ascii_case("TRUE"),
+        path: path.to_string(),
+        secure: secure.eq_ignore_ascii_case("TRUE"),
+        http_only,
+        expiration_date: (expires > 0).then_some(expires as f64),
+        name: name.to_string(),
+        value: value.to_string(),
+    }))
+}
+
+/// Adds a cookie to the store as if `Set-Cookie` had been received from its
+/// own domain, so the store applies its usual domain and path rules.
+fn insert(store: &mut CookieStore, cookie: &JsonCookie) -> Result<(), String> {
+    if cookie
+        .expiration_date
+        .is_some_and(|expires| expires < Utc::now().timestamp() as f64)
+    {
+        return Ok(());
+    }
+
+    let domain = cookie.domain.trim_start_matches('.');
+    let scheme = if cookie.secure { "https" } else { "http" };
+    let url = Url::parse(&format!("{}://{}{}", scheme, domain, cookie.path))
+        .map_err(|err| err.to_string())?;
+
+    let mut set_cookie = format!("{}={}; Path={}", cookie.name, cookie.value, cookie.path);
+    if !cookie.host_only {
+        set_cookie.push_str(&format!("; Domain={}", domain));
+    }
+    if let Some(expires) = cookie.expiration_date {
+        let expires = Utc
+            .timestamp_opt(expires as i64, 0)
+            .single()
+            .ok_or_else(|| format!("invalid expiry {}", expires))?;
+        set_cookie.push_str(
+            &expires
+                .format("; Expires=%a, %d %b %Y %H:%M:%S GMT")
+                .to_string(),
+        );
+    }
+    if cookie.secure {
+        set_cookie.push_str("; Secure");
+    }
+    if cookie.http_only {
+        set_cookie.push_str("; HttpOnly");
+    }
+
+    store
+        .parse(&set_cookie, &url)
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_path;
+
+    // 2100-01-01, so these cookies don't expire while the tests run
+    const FUTURE: i64 = 4102444800;
+
+    fn names(store: &CookieStore, url: &str) -> Vec<String> {
+        let mut names: Vec<String> = store
+            .matches(&Url::parse(url).unwrap())
+            .iter()
+            .map(|cookie| cookie.name().to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn loads_netscape_files_with_http_only_lines() {
+        let path = temp_path("load_cookies.txt");
+        fs::write(
+            &path,
+            format!(
+                "# Netscape HTTP Cookie File\n\
+                 \n\
+                 .example.com\tTRUE\t/\tFALSE\t{future}\tdomain\t1\n\
+                 #HttpOnly_example.com\tFALSE\t/\tTRUE\t{future}\tsession_id\tabc\n\
+                 example.com\tFALSE\t/account\tFALSE\t0\tsession\t2\n\
+                 example.com\tFALSE\t/\tFALSE\t1000000000\texpired\t3\n",
+                future = FUTURE
+            ),
+        )
+        .unwrap();
+
+        let store = load(path.to_str().unwrap()).unwrap();
+        let session_id = store.get("example.com", "/", "session_id").unwrap();
+        assert_eq!(session_id.value(), "abc");
+        assert_eq!(session_id.http_only(), Some(true));
+        assert_eq!(session_id.secure(), Some(true));
+
+        assert_eq!(
+            names(&store, "https://example.com/"),
+            ["domain", "session_id"]
+        );
+        assert_eq!(
+            names(&store, "http://example.com/account/1"),
+            ["domain", "session"]
+        );
+        // Host-only cookies stay off subdomains; the expired one is gone
+        assert_eq!(names(&store, "https://www.example.com/"), ["domain"]);
+        assert!(!store.contains_any("example.com", "/", "expired"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn reports_the_line_of_a_bad_netscape_cookie() {
+        let path = temp_path("bad_cookies.txt");
+        fs::write(
+            &path,
+            "# Netscape HTTP Cookie File\nexample.com\tFALSE\t/\n",
+        )
+        .unwrap();
+
+        let err = load(path.to_str().unwrap()).err().unwrap();
+        assert!(err.starts_with("Invalid cookie on line 2 of"), "{}", err);
+        assert!(
+            err.ends_with("expected 7 tab-separated fields, found 3"),
+            "{}",
+            err
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn loads_json_exports() {
+        let path = temp_path("load_cookies.json");
+        fs::write(
+            &path,
+            format!(
+                r#"[
+                    {{"domain": ".example.com", "name": "domain", "value": "1",
+                      "expirationDate": {}.5, "httpOnly": true}},
+                    {{"domain": "example.com", "hostOnly": true, "path": "/account",
+                      "secure": true, "name": "session", "value": "2"}}
+                ]"#,
+                FUTURE
+            ),
+        )
+        .unwrap();
+
+        let store = load(path.to_str().unwrap()).unwrap();
+        assert_eq!(
+            store.get("example.com", "/", "domain").unwrap().http_only(),
+            Some(true)
+        );
+        assert_eq!(
+            names(&store, "https://example.com/account"),
+            ["domain", "session"]
+        );
+        // Secure and host-only
+        assert_eq!(names(&store, "http://example.com/account"), ["domain"]);
+        assert_eq!(names(&store, "https://www.example.com/account"), ["domain"]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn saved_jars_load_back_the_same() {
+        let source = temp_path("source_cookies.txt");
+        fs::write(
+            &source,
+            format!(
+                ".example.com\tTRUE\t/\tFALSE\t{future}\tdomain\t1\n\
+                 #HttpOnly_example.com\tFALSE\t/account\tTRUE\t0\tsession\t2\n",
+                future = FUTURE
+            ),
+        )
+        .unwrap();
+        let store = load(source.to_str().unwrap()).unwrap();
+
+        let cookies = |store: &CookieStore| {
+            let mut cookies: Vec<String> = store
+                .iter_unexpired()
+                .map(|cookie| {
+                    format!(
+                        "{:?} {:?} {} {}={} secure={:?} http_only={:?}",
+                        cookie.domain,
+                        cookie.expires,
+                        cookie.path.as_ref(),
+                        cookie.name(),
+                        cookie.value(),
+                        cookie.secure(),
+                        cookie.http_only()
+                    )
+                })
+                .collect();
+            cookies.sort();
+            cookies
+        };
+
+        for name in ["saved_cookies.txt", "saved_cookies.json"] {
+            let path = temp_path(name);
+            save(&store, path.to_str().unwrap()).unwrap();
+            let reloaded = load(path.to_str().unwrap()).unwrap();
+            assert_eq!(cookies(&reloaded), cookies(&store), "{}", name);
+            fs::remove_file(&path).unwrap();
+        }
+
+        // Domain cookies get the leading dot, HttpOnly ones the prefix
+        let path = temp_path("saved_cookies.txt");
+        save(&store, path.to_str().unwrap()).unwrap();
+        let saved = fs::read_to_string(&path).unwrap();
+        assert!(saved.contains(&format!(
+            "\n.example.com\tTRUE\t/\tFALSE\t{}\tdomain\t1\n",
+            FUTURE
+        )));
+        assert!(saved.contains("\n#HttpOnly_example.com\tFALSE\t/account\tTRUE\t0\tsession\t2\n"));
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&source).unwrap();
+    }
+}
@@ -1,139 +1,305 @@
-use chrono::prelude::*;
-use rand::prelude::*;
-use reqwest::Url;
+use reqwest::{header::CONTENT_TYPE, Method, Url};
 use scraper::{Html, Selector};
 use std::{
-    collections::HashSet,
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
     fs::{self, File, OpenOptions},
     io::prelude::*,
     path::Path,
     sync::Arc,
 };
 use tokio::sync::Semaphore;
+use tracing::{debug, error_span, info, warn, Instrument};
 
-use crate::{config::ScraperConfig, find::Scraper};
+use crate::{
+    config::ScraperConfig,
+    content,
+    fetch::{FetchedPage, Fetcher},
+    find::Scraper,
+    graph::{self, LinkEdge, LinkGraph},
+    linkcheck::{self, LinkReport, LinkResult, Outcome},
+};
 
 pub struct Crawler {
     pub config: super::config::ScraperConfig,
-    pub scraper: Scraper,
+    pub scraper: Arc<Scraper>,
+    // Links count as internal when they share its host and port
+    base_url: Url,
     visited_urls: HashSet<String>,
     max_connections: Arc<Semaphore>,
-    proxies: Option<Vec<String>>,
+    fetcher: Arc<Fetcher>,
 }
 
 impl Crawler {
-    pub fn new(config: ScraperConfig, max_connections: usize, use_proxies: bool) -> Self {
-        let proxies = if use_proxies {
-            match std::fs::read_to_string("proxies.txt") {
-                Ok(content) => {
-                    let proxies: Vec<String> = content.lines().map(|s| s.to_string()).collect();
-                    Some(proxies)
-                }
-                Err(e) => {
-                    eprintln!("Error reading proxies.txt: {}", e);
-                    None
+    pub fn new(
+        config: ScraperConfig,
+        scraper: Arc<Scraper>,
+        fetcher: Arc<Fetcher>,
+        max_connections: usize,
+    ) -> Result<Self, String> {
+        let base_url = Url::parse(&config.base_url)
+            .map_err(|err| format!("Invalid base URL '{}': {}", config.base_url, err))?;
+        Ok(Crawler {
+            config,
+            scraper,
+            base_url,
+            visited_urls: HashSet::new(),
+            max_connections: Arc::new(Semaphore::new(max_connections)),
+            fetcher,
+        })
+    }
+
+    pub async fn run(&mut self, selectors: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+        // Spans are at error level so that even -qq output says where things went wrong
+        let span = error_span!("crawl", start = %self.config.start_url());
+        self.crawl(selectors).instrument(span).await
+    }
+
+    async fn crawl(&mut self, selectors: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+        info!(
+            host = self.base_url.host_str().unwrap_or_default(),
+            "Crawling, staying within the host"
+        );
+        // Pages still to visit, with their click depth and the page linking to them
+        let mut queue: Vec<(String, usize, Option<String>)> =
+            vec![(self.config.start_url(), 0, None)];
+
+        // Files from this crawl are named like the rest of the run's results
+        let timestamp = self.scraper.run_timestamp();
+        let mut link_graph = LinkGraph::new(&self.config.start_url());
+        let mut pages = 0;
+
+        while let Some((url, depth, referrer)) = queue.pop() {
+            self.fetcher.stats().set_queued(queue.len());
+            if self
+                .config
+                .max_pages
+                .is_some_and(|max_pages| pages >= max_pages)
+            {
+                info!(pages, "Page budget reached, stopping the crawl");
+                break;
+            }
+            if self.visited_urls.insert(url.clone()) {
+                pages += 1;
+                let span = error_span!("page", url = %url, depth);
+                let links = self
+                    .visit(
+                        &url,
+                        depth,
+                        referrer.as_deref(),
+                        selectors,
+                        &timestamp,
+                        &mut link_graph,
+                    )
+                    .instrument(span)
+                    .await?;
+                for link in links {
+                    queue.push((link, depth + 1, Some(url.clone())));
                 }
             }
+        }
+
+        if !self.config.link_graph.is_empty() {
+            let sitemap = graph::fetch_sitemap(&self.fetcher, &self.config.sitemap_url).await;
+            link_graph.export(&self.config.link_graph, &sitemap, &timestamp)?;
+        }
+
+        Ok(())
+    }
+
+    /// Fetches, saves and scrapes one page, returning the new links to visit.
+    async fn visit(
+        &mut self,
+        url: &str,
+        depth: usize,
+        referrer: Option<&str>,
+        selectors: &[String],
+        timestamp: &str,
+        link_graph: &mut LinkGraph,
+    ) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let _permit = self.max_connections.acquire().await;
+
+        let page = match self.fetcher.fetch(url).await {
+            Ok(page) => page,
+            Err(err) => {
+                warn!(error = %err, "Skipping page");
+                return Ok(Vec::new());
+            }
+        };
+        self.scraper.record_page(&page, depth, referrer)?;
+        link_graph.add_page(url);
+        // Redirect targets count as visited so they are not fetched twice
+        self.visited_urls.insert(page.final_url.clone());
+
+        if !page.is_success() {
+            warn!(status = page.status, "Skipping page");
+            return Ok(Vec::new());
+        }
+        // Only HTML is parsed for links; other content is just saved or scraped
+        let links = if page.is_html() {
+            extract_links(&self.base_url, &page.final_url, url, &page.body, false)
         } else {
-            None
+            Vec::new()
         };
-        Crawler {
-            config: config.clone(),
-            scraper: Scraper::new(config),
-            visited_urls: HashSet::new(),
-            max_connections: Arc::new(Semaphore::new(max_connections)),
-            proxies,
+
+        if self.config.full_download {
+            self.save_page(url, &page)?;
+        }
+
+        let mut new_links = Vec::new();
+        for link in links {
+            self.scraper.record_link(&link)?;
+            if !self.visited_urls.contains(&link.target) {
+                new_links.push(link.target.clone());
+                self.save_crawl_results_to_file(std::slice::from_ref(&link.target), timestamp)
+                    .await?;
+            }
+            link_graph.add_edge(link);
+        }
+        debug!(links = new_links.len(), "Links queued");
+
+        if page.is_text() {
+            // Crawls run with either CSS selectors or regex patterns
+            if self.config.use_regex {
+                self.scraper
+                    .scrape_data_with_regex(&page, selectors)
+                    .await?;
+            } else {
+                self.scraper.scrape_data(&page, selectors).await?;
+            }
+            if self.config.metadata {
+                self.scraper.scrape_metadata(&page).await?;
+            }
+            self.scraper.scrape_tables(&page).await?;
         }
+
+        Ok(new_links)
     }
 
-    pub async fn run(&mut self, selectors: &[String]) -> Result<(), Box<dyn std::error::Error>> {
-        let mut queue: Vec<String> = vec![self.config.base_url.clone()];
+    /// Crawls the site recording the status of every internal link, and of
+    /// external links too when `check_external` is set, without crawling them.
+    pub async fn check_links(&mut self) -> Result<LinkReport, Box<dyn std::error::Error>> {
+        let span = error_span!("link_check", start = %self.config.start_url());
+        self.check_site_links().instrument(span).await
+    }
 
-        // Get the current timestamp and format it
-        let timestamp = Local::now().format("%Y%m%d%H%M%S").to_string();
+    async fn check_site_links(&mut self) -> Result<LinkReport, Box<dyn std::error::Error>> {
+        let mut queue = VecDeque::from([self.config.start_url()]);
+        let mut referrers: HashMap<String, BTreeSet<String>> = HashMap::new();
+        let mut external_links = BTreeSet::new();
+        let mut checks = Vec::new();
 
-        while let Some(url) = queue.pop() {
-            if self.visited_urls.insert(url.clone()) {
-                let _permit = self.max_connections.acquire().await;
+        while let Some(url) = queue.pop_front() {
+            self.fetcher.stats().set_queued(queue.len());
+            if !self.visited_urls.insert(url.clone()) {
+                continue;
+            }
 
-                println!("Visiting: {}", url);
-                let html = self.send_request(&url).await?;
-                let links = self.extract_links(&html);
+            let mut check = linkcheck::check_url(&self.fetcher, &url, Method::GET)
+                .instrument(error_span!("page", url = %url))
+                .await;
 
-                if self.config.full_download {
-                    self.save_html(&url, &html)?;
-                }
+            // A redirect's target is checked and crawled as a page of its own
+            if !check.redirects.is_empty()
+                && check.response.is_some()
+                && self.is_internal(&check.final_url)
+                && !self.visited_urls.contains(&check.final_url)
+            {
+                queue.push_back(check.final_url.clone());
+            }
 
-                for link in links {
-                    if !self.visited_urls.contains(&link) {
-                        queue.push(link.clone());
-                        self.save_crawl_results_to_file(&[link], &timestamp).await?;
+            // Only pages that load directly and are on the site get their links followed
+            let html = match check.response.take() {
+                Some(response)
+                    if check.outcome == Outcome::Ok
+                        && self.is_internal(&check.final_url)
+                        && response
+                            .headers()
+                            .get(CONTENT_TYPE)
+                            .and_then(|value| value.to_str().ok())
+                            .is_some_and(|value| value.contains("html")) =>
+                {
+                    match self.fetcher.read_response(response, &url).await {
+                        Ok(page) if page.is_html() => Some(page.body),
+                        Ok(_) => None,
+                        Err(err) => {
+                            warn!(url = %url, error = %err, "Not following the links of the page");
+                            None
+                        }
                     }
                 }
+                _ => None,
+            };
+            checks.push((url.clone(), check, false));
 
-                let scraper = Scraper::new(self.config.clone());
-                scraper.scrape_data(selectors).await?;
+            let html = match html {
+                Some(html) => html,
+                None => continue,
+            };
+            for link in extract_links(
+                &self.base_url,
+                &url,
+                &url,
+                &html,
+                self.config.check_external,
+            ) {
+                referrers
+                    .entry(link.target.clone())
+                    .or_default()
+                    .insert(url.clone());
 
-                drop(_permit);
+                if !self.is_internal(&link.target) {
+                    external_links.insert(link.target);
+                } else if !self.visited_urls.contains(&link.target) {
+                    queue.push_back(link.target);
+                }
             }
         }
 
-        Ok(())
+        for url in external_links {
+            let check = linkcheck::check_url(&self.fetcher, &url, Method::HEAD)
+                .instrument(error_span!("page", url = %url, external = true))
+                .await;
+            checks.push((url, check, true));
+        }
+
+        let mut report = LinkReport::default();
+        for (url, check, external) in checks {
+            let referrers = referrers.remove(&url).unwrap_or_default();
+            report.add(LinkResult {
+                url,
+                outcome: check.outcome,
+                status: check.status,
+                redirects: check.redirects,
+                error: check.error,
+                external,
+                referrers: referrers.into_iter().collect(),
+            });
+        }
+
+        Ok(report)
     }
 
-    fn extract_links(&self, html: &str) -> Vec<String> {
-        let document = Html::parse_document(html);
-        let a_selector = Selector::parse("a").unwrap();
-
-        let base_url = Url::parse(&self.config.base_url).unwrap();
-        let base_domain = base_url.domain().unwrap();
-
-        println!("Staying within domain: {}", base_domain);
-
-        document
-            .select(&a_selector)
-            .filter_map(|element| element.value().attr("href"))
-            .filter(|link| !link.starts_with("javascript:"))
-            .filter(|link| !link.starts_with("mailto:"))
-            .filter(|link| !link.starts_with("#"))
-            .filter(|link| !link.starts_with("tel:"))
-            .filter(|link| !link.starts_with("data:"))
-            .filter(|link| !link.starts_with("ftp:"))
-            .filter(|link| !link.starts_with("file:"))
-            .filter(|link| !link.starts_with("sms:"))
-            .filter(|link| !link.starts_with("skype:"))
-            .filter(|link| !link.starts_with("whatsapp:"))
-            .filter(|link| !link.starts_with("viber:"))
-            .filter(|link| !link.starts_with("intent:"))
-            .filter(|link| !link.starts_with("geo:"))
-            .filter(|link| !link.starts_with("magnet:"))
-            .filter(|link| !link.starts_with("bitcoin:"))
-            .filter(|link| !link.starts_with("spotify:"))
-            .filter(|link| !link.starts_with("steam:"))
-            .map(|link| {
-                Url::parse(&self.config.base_url)
-                    .unwrap()
-                    .join(link)
-                    .unwrap()
-            })
-            .filter(|url| url.domain().map_or(false, |domain| domain == base_domain))
-            .map(|url| url.to_string())
-            .collect()
+    fn is_internal(&self, url: &str) -> bool {
+        Url::parse(url).is_ok_and(|url| same_site(&self.base_url, &url))
     }
 
-    fn save_html(&self, url: &str, html: &str) -> std::io::Result<()> {
+    fn save_page(&self, url: &str, page: &FetchedPage) -> std::io::Result<()> {
         // Create the downloads folder if it doesn't exist
         let folder_path = Path::new(&self.config.download_folder);
         if !folder_path.exists() {
             fs::create_dir_all(folder_path)?;
         }
 
+        // Name the file after what the body is, falling back to the URL's extension
         let parsed_url = Url::parse(url).unwrap();
         let file_path = parsed_url.path();
-        let file_extension = Path::new(file_path)
-            .extension()
-            .and_then(|ext| ext.to_str())
-            .unwrap_or("html");
+        let file_extension = content::extension_for(&page.mime)
+            .or_else(|| {
+                Path::new(file_path)
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+            })
+            .unwrap_or("bin");
 
         // Change the format of the file name to include the folder and file extension
         let file_name = format!(
@@ -144,7 +310,7 @@ impl Crawler {
             file_extension
         );
         let mut file = File::create(file_name)?;
-        file.write_all(html.as_bytes())?;
+        file.write_all(&page.bytes)?;
 
         Ok(()) // Return an empty Ok result
     }
@@ -162,7 +328,6 @@ impl Crawler {
 
         // Open the file with the new file path
         let mut file = OpenOptions::new()
-            .write(true)
             .append(true)
             .create(true)
             .open(file_path)?;
@@ -174,19 +339,210 @@ impl Crawler {
 
         Ok(())
     }
+}
 
-    async fn send_request(&self, url: &str) -> Result<String, Box<dyn std::error::Error>> {
-        let client = if let Some(ref proxies) = self.proxies {
-            let proxy_url = proxies.as_slice().choose(&mut rand::thread_rng()).unwrap();
-            let proxy = reqwest::Proxy::all(proxy_url)?;
-            println!("Using proxy: {}", proxy_url);
-            reqwest::Client::builder().proxy(proxy).build()?
-        } else {
-            reqwest::Client::new()
-        };
+/// Links on a page, resolved against its `<base href>` or else `page_url`
+/// (the URL the page was finally served from) and recorded as edges from
+/// `source`. Besides anchors this picks up image map areas, iframes,
+/// `link[rel=next]` and meta refresh targets. Only links on the same site as
+/// `base_url` are kept unless `include_external` is set.
+fn extract_links(
+    base_url: &Url,
+    page_url: &str,
+    source: &str,
+    html: &str,
+    include_external: bool,
+) -> Vec<LinkEdge> {
+    let document = Html::parse_document(html);
+    let link_selector = Selector::parse(
+        "a[href], area[href], link[rel~=next][href], iframe[src], meta[http-equiv][content]",
+    )
+    .unwrap();
+
+    let page_url = match Url::parse(page_url) {
+        Ok(page_url) => page_url,
+        Err(_) => return Vec::new(),
+    };
+    let document_url = document_base(&document, &page_url);
+
+    document
+        .select(&link_selector)
+        .filter_map(|element| {
+            let value = element.value();
+            let (link, anchor_text) = match value.name() {
+                "a" => (
+                    value.attr("href")?.to_string(),
+                    element.text().collect::<String>(),
+                ),
+                "area" => (
+                    value.attr("href")?.to_string(),
+                    value.attr("alt").unwrap_or_default().to_string(),
+                ),
+                "iframe" => (
+                    value.attr("src")?.to_string(),
+                    value.attr("title").unwrap_or_default().to_string(),
+                ),
+                "meta" => (refresh_target(value)?, String::new()),
+                _ => (value.attr("href")?.to_string(), String::new()),
+            };
+            Some((element, link.trim().to_string(), anchor_text))
+        })
+        .filter(|(_, link, _)| !link.is_empty() && !link.starts_with('#'))
+        .filter_map(
+            |(element, link, anchor_text)| match document_url.join(&link) {
+                Ok(url) => Some((element, url, anchor_text)),
+                Err(err) => {
+                    warn!(link = %link, page = %page_url, error = %err, "Skipping malformed link");
+                    None
+                }
+            },
+        )
+        // Also drops javascript:, mailto:, tel: and other non-web links
+        .filter(|(_, url, _)| matches!(url.scheme(), "http" | "https"))
+        .filter(|(_, url, _)| include_external || same_site(base_url, url))
+        .map(|(element, mut url, anchor_text)| {
+            // Fragments point into the same document, so each page is one target
+            url.set_fragment(None);
+            LinkEdge {
+                source: source.to_string(),
+                target: url.to_string(),
+                anchor_text: anchor_text.split_whitespace().collect::<Vec<_>>().join(" "),
+                rel: element.value().attr("rel").map(str::to_string),
+            }
+        })
+        .collect()
+}
+
+/// Whether `url` is on the crawled site: the same host as `base`, IP
+/// addresses included, and the same explicit port. http and https links to
+/// the host both count.
+fn same_site(base: &Url, url: &Url) -> bool {
+    url.host_str() == base.host_str() && url.port() == base.port()
+}
+
+/// The URL relative links in a document resolve against: its first
+/// `<base href>`, itself resolved against the page URL, or the page URL.
+fn document_base(document: &Html, page_url: &Url) -> Url {
+    let base_selector = Selector::parse("base[href]").unwrap();
+    let href = match document
+        .select(&base_selector)
+        .next()
+        .and_then(|base| base.value().attr("href"))
+    {
+        Some(href) => href.trim(),
+        None => return page_url.clone(),
+    };
+
+    match page_url.join(href) {
+        Ok(base) => base,
+        Err(err) => {
+            warn!(href = %href, page = %page_url, error = %err, "Ignoring malformed <base href>");
+            page_url.clone()
+        }
+    }
+}
+
+/// The target of a `<meta http-equiv="refresh" content="5; url=...">` tag.
+fn refresh_target(meta: &scraper::node::Element) -> Option<String> {
+    if !meta.attr("http-equiv")?.eq_ignore_ascii_case("refresh") {
+        return None;
+    }
+    let (_, target) = meta.attr("content")?.split_once(';')?;
+    let target = target.trim();
+    let target = match target.get(..3) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("url") => {
+            target[3..].trim_start().strip_prefix('=').unwrap_or(target)
+        }
+        _ => target,
+    };
+    Some(target.trim().trim_matches(['\'', '"']).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    fn refresh(content: &str) -> Option<String> {
+        let html = format!(r#"<meta http-equiv="Refresh" content="{}">"#, content);
+        let document = Html::parse_document(&html);
+        let meta = document
+            .select(&Selector::parse("meta").unwrap())
+            .next()
+            .unwrap();
+        refresh_target(meta.value())
+    }
+
+    #[test]
+    fn same_site_compares_host_and_port() {
+        let base = url("http://127.0.0.1:8080/");
+        assert!(same_site(&base, &url("http://127.0.0.1:8080/page")));
+        assert!(!same_site(&base, &url("http://127.0.0.1/page")));
+        assert!(!same_site(&base, &url("http://127.0.0.2:8080/page")));
+
+        let base = url("http://example.com/");
+        assert!(same_site(&base, &url("https://example.com/page")));
+        assert!(same_site(&base, &url("http://example.com:80/page")));
+        assert!(!same_site(&base, &url("http://www.example.com/page")));
+        assert!(!same_site(&url("http://[::1]/"), &base));
+    }
+
+    #[test]
+    fn document_base_prefers_the_first_base_href() {
+        let page = url("https://example.com/blog/post.html");
+        let document =
+            Html::parse_document(r#"<head><base href=" /static/ "><base href="/other/"></head>"#);
+        assert_eq!(
+            document_base(&document, &page).as_str(),
+            "https://example.com/static/"
+        );
+
+        let document = Html::parse_document("<p>No base here</p>");
+        assert_eq!(document_base(&document, &page), page);
+
+        let document = Html::parse_document(r#"<base href="http://[bad">"#);
+        assert_eq!(document_base(&document, &page), page);
+    }
+
+    #[test]
+    fn refresh_target_reads_the_url_part() {
+        assert_eq!(refresh("5; url=/next").as_deref(), Some("/next"));
+        assert_eq!(refresh("0;URL = 'next.html'").as_deref(), Some("next.html"));
+        assert_eq!(
+            refresh("0; &quot;/quoted&quot;").as_deref(),
+            Some("/quoted")
+        );
+        assert_eq!(refresh("30"), None);
 
-        let response = client.get(url).send().await?;
-        let html = response.text().await?;
-        Ok(html)
+        let document = Html::parse_document(
+            r#"<meta http-equiv="content-type" content="text/html; charset=utf-8">"#,
+        );
+        let meta = document
+            .select(&Selector::parse("meta").unwrap())
+            .next()
+            .unwrap();
+        assert_eq!(refresh_target(meta.value()), None);
+    }
+
+    #[test]
+    fn extract_links_drops_fragments() {
+        let html = r##"<a href="/page#a">A</a> <a href="page#b">B</a> <a href="#top">Top</a>
+            <a href="https://other.example/#x">Other</a>"##;
+        let links = extract_links(
+            &url("https://example.com/"),
+            "https://example.com/index.html",
+            "https://example.com/",
+            html,
+            false,
+        );
+
+        let targets: Vec<&str> = links.iter().map(|link| link.target.as_str()).collect();
+        assert_eq!(
+            targets,
+            ["https://example.com/page", "https://example.com/page"]
+        );
     }
 }
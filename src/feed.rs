@@ -0,0 +1,69 @@
+use serde::{Deserialize, Serialize};
+use sxd_xpath::Value;
+
+/// A feed entry normalized across RSS 2.0 and Atom.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FeedEntry {
+    pub id: String,
+    pub title: Option<String>,
+    pub link: Option<String>,
+    pub published: Option<String>,
+    pub author: Option<String>,
+    pub summary: Option<String>,
+}
+
+pub fn parse_feed(body: &[u8]) -> Result<Vec<FeedEntry>, Box<dyn std::error::Error>> {
+    let feed = feed_rs::parser::parse(body)?;
+
+    let entries = feed
+        .entries
+        .into_iter()
+        .map(|entry| {
+            // Prefer the alternate link, which is the article itself in Atom feeds
+            let link = entry
+                .links
+                .iter()
+                .find(|link| link.rel.as_deref().is_none_or(|rel| rel == "alternate"))
+                .or_else(|| entry.links.first())
+                .map(|link| link.href.clone());
+            let summary = entry
+                .summary
+                .map(|text| text.content)
+                .or_else(|| entry.content.and_then(|content| content.body));
+
+            FeedEntry {
+                id: entry.id,
+                title: entry.title.map(|text| text.content.trim().to_string()),
+                link,
+                published: entry
+                    .published
+                    .or(entry.updated)
+                    .map(|date| date.to_rfc3339()),
+                author: entry.authors.into_iter().find_map(|person| person.name),
+                summary: summary.map(|text| text.trim().to_string()),
+            }
+        })
+        .collect();
+
+    Ok(entries)
+}
+
+/// Evaluates an XPath expression against an XML document. Node sets yield one
+/// string per node; scalar results yield a single string.
+pub fn evaluate_xpath(xml: &str, expression: &str) -> Result<Vec<String>, String> {
+    let package = sxd_document::parser::parse(xml)
+        .map_err(|err| format!("Failed to parse XML document: {}", err))?;
+    let document = package.as_document();
+
+    let value = sxd_xpath::evaluate_xpath(&document, expression)
+        .map_err(|err| format!("Invalid XPath '{}': {}", expression, err))?;
+
+    Ok(match value {
+        Value::Nodeset(nodes) => nodes
+            .document_order()
+            .into_iter()
+            .map(|node| node.string_value().trim().to_string())
+            .collect(),
+        other => vec![other.into_string()],
+    })
+}
@@ -0,0 +1,547 @@
+use chrono::prelude::*;
+use encoding_rs::Encoding;
+use rand::seq::SliceRandom;
+use reqwest::{
+    header::{HeaderMap, CONTENT_TYPE, USER_AGENT},
+    Method,
+};
+use reqwest_cookie_store::CookieStoreMutex;
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::{debug, error_span, info, warn, Instrument};
+
+use crate::{
+    auth::HostAuth,
+    charset::{self, Source},
+    config::{self, ScraperConfig},
+    content::{self, ContentPolicy},
+    cookies,
+    proxy::{self, ProxyPool, ProxySettings},
+    stats::RunStats,
+    tls::TlsSettings,
+};
+
+/// Timeouts and redirect handling applied to every request.
+#[derive(Clone, Debug)]
+pub struct FetchSettings {
+    pub connect_timeout: Duration,
+    // Longest wait for the next chunk of a response
+    pub read_timeout: Duration,
+    // Limit for a whole request, body included
+    pub timeout: Duration,
+    pub max_redirects: usize,
+    // Sent with every request: -H headers, Accept-Language and Referer
+    pub headers: HeaderMap,
+    // User-Agent strings to pick from; empty means DEFAULT_USER_AGENT
+    pub user_agents: Vec<String>,
+    // Pick a User-Agent for every request instead of once per session
+    pub rotate_user_agent_per_request: bool,
+    // Cookie jar loaded at startup, if it exists, and saved after every run
+    pub cookie_file: Option<String>,
+    // Basic or Bearer credentials by host, from the config file
+    pub auth: Vec<(String, HostAuth)>,
+    pub proxy: ProxySettings,
+    pub tls: TlsSettings,
+    // TLS settings by host from the config file, with the global ones merged in
+    pub host_tls: Vec<(String, TlsSettings)>,
+}
+
+pub const DEFAULT_USER_AGENT: &str =
+    concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// Which pooled client a request goes out on.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct ClientKey {
+    proxy: Option<String>,
+    // The config file host whose TLS settings apply, if any
+    tls_host: Option<String>,
+    // Link checking follows redirects itself and has its own timeout
+    link_check: bool,
+}
+
+/// The HTTP layer shared by the crawler, the scraper and the other modes.
+/// Clients are built once per proxy and TLS setup and reused, so
+/// connections are pooled.
+pub struct Fetcher {
+    settings: FetchSettings,
+    policy: ContentPolicy,
+    link_timeout: Duration,
+    proxies: Option<ProxyPool>,
+    clients: Mutex<HashMap<ClientKey, reqwest::Client>>,
+    // Used for every request unless rotating per request
+    session_user_agent: String,
+    // Shared by every client, so all modes and interval runs keep one session
+    cookies: Arc<CookieStoreMutex>,
+    stats: RunStats,
+}
+
+impl Fetcher {
+    pub fn new(config: &ScraperConfig) -> Result<Self, String> {
+        let proxies = match &config.fetch.proxy.file {
+            Some(path) => {
+                let content = std::fs::read_to_string(path)
+                    .map_err(|err| format!("Error reading {}: {}", path, err))?;
+                let proxies = proxy::parse_list(&content)
+                    .map_err(|err| format!("Invalid proxy file {}: {}", path, err))?;
+                if proxies.is_empty() {
+                    warn!(file = %path, "The proxy file lists no proxies, connecting directly");
+                }
+                Some(ProxyPool::new(proxies, config.fetch.proxy.clone()))
+            }
+            None => {
+                // reqwest picks these up by itself when no proxy is set on a client
+                if let Some((name, value)) = proxy::from_env() {
+                    info!(variable = name, proxy = %proxy::redact(&value), "Using proxy from the environment");
+                }
+                None
+            }
+        };
+
+        let session_user_agent = pick_user_agent(&config.fetch.user_agents);
+        if !config.fetch.rotate_user_agent_per_request {
+            debug!(user_agent = %session_user_agent, "Using User-Agent");
+        }
+
+        let cookies = match &config.fetch.cookie_file {
+            Some(path) if Path::new(path).exists() => {
+                let store = cookies::load(path)?;
+                info!(
+                    cookies = store.iter_unexpired().count(),
+                    file = %path,
+                    "Loaded cookies"
+                );
+                store
+            }
+            _ => Default::default(),
+        };
+
+        let fetcher = Fetcher {
+            settings: config.fetch.clone(),
+            policy: config.content_policy.clone(),
+            link_timeout: config.link_timeout,
+            proxies,
+            clients: Mutex::new(HashMap::new()),
+            session_user_agent,
+            cookies: Arc::new(CookieStoreMutex::new(cookies)),
+            stats: RunStats::new(config.progress),
+        };
+
+        // Build every TLS setup once, so unreadable certificates fail at startup
+        if fetcher.settings.tls.is_insecure() {
+            warn_insecure("all hosts");
+        }
+        fetcher
+            .client_builder(None, &fetcher.settings.tls)
+            .and_then(|builder| Ok(builder.build()?))
+            .map_err(|err| err.to_string())?;
+        for (host, tls) in &fetcher.settings.host_tls {
+            if tls.is_insecure() && !fetcher.settings.tls.is_insecure() {
+                warn_insecure(host);
+            }
+            fetcher
+                .client_builder(None, tls)
+                .and_then(|builder| Ok(builder.build()?))
+                .map_err(|err| format!("TLS settings for {}: {}", host, err))?;
+        }
+
+        Ok(fetcher)
+    }
+
+    /// Counters for the current run, fed by every request.
+    pub fn stats(&self) -> &RunStats {
+        &self.stats
+    }
+
+    /// Writes the cookie jar back to the `--cookies` file, if one is set.
+    pub fn save_cookies(&self) -> std::io::Result<()> {
+        if let Some(path) = &self.settings.cookie_file {
+            cookies::save(&self.cookies.lock().unwrap(), path)?;
+        }
+        Ok(())
+    }
+
+    /// The proxy from the proxy file for a request to `url`, unless proxies
+    /// are off or `NO_PROXY` exempts its host.
+    pub fn pick_proxy(&self, url: &str) -> Option<String> {
+        let host = reqwest::Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+            .unwrap_or_default();
+        if proxy::bypasses(&self.settings.proxy.no_proxy, &host) {
+            return None;
+        }
+        self.proxies.as_ref()?.pick(&host)
+    }
+
+    fn record_stats(&self, result: &Result<FetchedPage, Box<dyn std::error::Error>>) {
+        match result {
+            Ok(page) => self.stats.record_response(
+                &page.url,
+                page.status,
+                page.bytes.len() as u64,
+                page.elapsed,
+            ),
+            Err(err) => self.stats.record_error(err.as_ref()),
+        }
+    }
+
+    /// Updates the health of the proxy a request went through. Errors from
+    /// the connection and 407 responses count against it; errors such as a
+    /// rejected content type do not.
+    fn record_proxy(
+        &self,
+        proxy: Option<&str>,
+        result: &Result<FetchedPage, Box<dyn std::error::Error>>,
+    ) {
+        if let (Some(pool), Some(proxy)) = (&self.proxies, proxy) {
+            let ok = match result {
+                Ok(page) => page.status != 407,
+                Err(err) => !err.is::<reqwest::Error>(),
+            };
+            pool.record(proxy, ok);
+        }
+    }
+
+    /// A client builder with the configured timeouts, redirect limit,
+    /// decompression, TLS setup and the shared cookie jar, going through
+    /// `proxy` if given.
+    fn client_builder(
+        &self,
+        proxy: Option<&str>,
+        tls: &TlsSettings,
+    ) -> Result<reqwest::ClientBuilder, Box<dyn std::error::Error>> {
+        let mut builder = reqwest::Client::builder()
+            .connect_timeout(self.settings.connect_timeout)
+            .read_timeout(self.settings.read_timeout)
+            .timeout(self.settings.timeout)
+            .redirect(reqwest::redirect::Policy::limited(
+                self.settings.max_redirects,
+            ))
+            .gzip(true)
+            .brotli(true)
+            .deflate(true)
+            .default_headers(self.settings.headers.clone())
+            .user_agent(self.session_user_agent.as_str())
+            .cookie_provider(self.cookies.clone());
+        if let Some(proxy) = proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+        }
+        Ok(tls.apply(builder)?)
+    }
+
+    /// The pooled client for requests to `url` through `proxy`, or directly.
+    fn client(
+        &self,
+        proxy: Option<&str>,
+        url: &str,
+        link_check: bool,
+    ) -> Result<reqwest::Client, Box<dyn std::error::Error>> {
+        let (tls_host, tls) = match config::for_host(&self.settings.host_tls, url) {
+            Some((host, tls)) => (Some(host.to_string()), tls),
+            None => (None, &self.settings.tls),
+        };
+        let key = ClientKey {
+            proxy: proxy.map(str::to_string),
+            tls_host,
+            link_check,
+        };
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(client) = clients.get(&key) {
+            return Ok(client.clone());
+        }
+
+        let mut builder = self.client_builder(proxy, tls)?;
+        if link_check {
+            builder = builder
+                .redirect(reqwest::redirect::Policy::none())
+                .timeout(self.link_timeout);
+        }
+        let client = builder.build()?;
+        clients.insert(key, client.clone());
+        Ok(client)
+    }
+
+    /// A client builder for posting to a webhook at `url`, with the proxy and
+    /// TLS setup for its host but none of the scraping headers or cookies.
+    pub fn webhook_client_builder(
+        &self,
+        url: &str,
+    ) -> Result<reqwest::ClientBuilder, Box<dyn std::error::Error>> {
+        let tls = match config::for_host(&self.settings.host_tls, url) {
+            Some((_, tls)) => tls,
+            None => &self.settings.tls,
+        };
+        let mut builder = reqwest::Client::builder()
+            .connect_timeout(self.settings.connect_timeout)
+            .user_agent(DEFAULT_USER_AGENT);
+        if let Some(proxy) = self.pick_proxy(url) {
+            builder = builder.proxy(reqwest::Proxy::all(proxy.as_str())?);
+        }
+        Ok(tls.apply(builder)?)
+    }
+
+    /// A client for checking `url` that does not follow redirects and uses
+    /// the link check timeout.
+    pub fn link_check_client(
+        &self,
+        url: &str,
+    ) -> Result<reqwest::Client, Box<dyn std::error::Error>> {
+        self.client(self.pick_proxy(url).as_deref(), url, true)
+    }
+
+    /// A request on `client` with the per-request User-Agent and the
+    /// credentials configured for the URL's host.
+    pub fn request(
+        &self,
+        client: &reqwest::Client,
+        method: Method,
+        url: &str,
+    ) -> reqwest::RequestBuilder {
+        let mut request = client.request(method, url);
+        if self.settings.rotate_user_agent_per_request {
+            request = request.header(USER_AGENT, pick_user_agent(&self.settings.user_agents));
+        }
+        match config::for_host(&self.settings.auth, url) {
+            Some((_, auth)) => auth.apply(request),
+            None => request,
+        }
+    }
+
+    pub async fn fetch(&self, url: &str) -> Result<FetchedPage, Box<dyn std::error::Error>> {
+        let proxy = self.pick_proxy(url);
+        let request = self.request(
+            &self.client(proxy.as_deref(), url, false)?,
+            Method::GET,
+            url,
+        );
+        let span = request_span(&Method::GET, url, proxy.as_deref());
+        let result = FetchedPage::fetch(request, url, &self.policy)
+            .instrument(span)
+            .await;
+        self.stats.record_page();
+        self.record_stats(&result);
+        self.record_proxy(proxy.as_deref(), &result);
+        result
+    }
+
+    /// Reads the body of a response sent on one of `link_check_client`'s
+    /// clients the way `fetch` reads pages, under the content policy. Only
+    /// the bytes are counted, as the response was recorded when it came in.
+    pub async fn read_response(
+        &self,
+        response: reqwest::Response,
+        url: &str,
+    ) -> Result<FetchedPage, Box<dyn std::error::Error>> {
+        let span = request_span(&Method::GET, url, None);
+        let result = FetchedPage::read(response, url, &self.policy, Local::now(), Instant::now())
+            .instrument(span)
+            .await;
+        if let Ok(page) = &result {
+            self.stats.record_bytes(page.bytes.len() as u64);
+        }
+        result
+    }
+
+    /// Submits form fields to `url`, in the query string for GET and as an
+    /// urlencoded body otherwise.
+    pub async fn submit_form(
+        &self,
+        method: Method,
+        url: &str,
+        fields: &[(String, String)],
+    ) -> Result<FetchedPage, Box<dyn std::error::Error>> {
+        let proxy = self.pick_proxy(url);
+        let request = self.request(
+            &self.client(proxy.as_deref(), url, false)?,
+            method.clone(),
+            url,
+        );
+        let span = request_span(&method, url, proxy.as_deref());
+        let request = if method == Method::GET {
+            request.query(fields)
+        } else {
+            request.form(fields)
+        };
+        let result = FetchedPage::fetch(request, url, &self.policy)
+            .instrument(span)
+            .await;
+        self.stats.record_page();
+        self.record_stats(&result);
+        self.record_proxy(proxy.as_deref(), &result);
+        result
+    }
+}
+
+fn request_span(method: &Method, url: &str, proxy: Option<&str>) -> tracing::Span {
+    error_span!(
+        "request",
+        method = %method,
+        url = %url,
+        proxy = proxy.map(proxy::redact).as_deref()
+    )
+}
+
+fn warn_insecure(hosts: &str) {
+    warn!(
+        hosts,
+        "TLS certificate verification is disabled. Anyone on the network path can read and alter this traffic."
+    );
+}
+
+fn pick_user_agent(user_agents: &[String]) -> String {
+    user_agents
+        .choose(&mut rand::thread_rng())
+        .cloned()
+        .unwrap_or_else(|| DEFAULT_USER_AGENT.to_string())
+}
+
+/// A fetched page together with the response details the extractors and the
+/// crawl database need.
+pub struct FetchedPage {
+    // The URL that was requested
+    pub url: String,
+    // Where the request ended up after redirects; relative links resolve against it
+    pub final_url: String,
+    pub status: u16,
+    pub headers: HeaderMap,
+    // Detected from the body's magic bytes, falling back to the Content-Type header
+    pub mime: String,
+    pub bytes: Vec<u8>,
+    // The body as text; empty for binary content
+    pub body: String,
+    // The text encoding and where it was found; None for binary content
+    pub charset: Option<(&'static Encoding, Source)>,
+    pub fetched_at: DateTime<Local>,
+    // Time from sending the request until the whole body was read
+    pub elapsed: Duration,
+}
+
+impl FetchedPage {
+    /// Sends `request` for `url`, timing it and logging one event with its
+    /// status and latency. Responses whose type the policy rejects, or that
+    /// grow past its size limit, are abandoned with an error.
+    async fn fetch(
+        request: reqwest::RequestBuilder,
+        url: &str,
+        policy: &ContentPolicy,
+    ) -> Result<FetchedPage, Box<dyn std::error::Error>> {
+        let fetched_at = Local::now();
+        let started = Instant::now();
+        let response = request.send().await?;
+        FetchedPage::read(response, url, policy, fetched_at, started).await
+    }
+
+    /// Reads the body of `response` under the content policy, decoding text
+    /// by its charset, and logs the page.
+    async fn read(
+        mut response: reqwest::Response,
+        url: &str,
+        policy: &ContentPolicy,
+        fetched_at: DateTime<Local>,
+        started: Instant,
+    ) -> Result<FetchedPage, Box<dyn std::error::Error>> {
+        let status = response.status().as_u16();
+        let final_url = response.url().to_string();
+        let headers = response.headers().clone();
+
+        // Check what the server says it is before downloading anything
+        let content_type = headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok());
+        let declared = content_type.map(content::header_mime);
+        if let Some(declared) = &declared {
+            check_allowed(policy, declared)?;
+        }
+        let declared = declared.unwrap_or_else(|| "application/octet-stream".to_string());
+        let limit = policy.max_size(&declared);
+        if let (Some(length), Some(limit)) = (response.content_length(), limit) {
+            check_size(&declared, length, limit)?;
+        }
+
+        let mut bytes = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            bytes.extend_from_slice(&chunk);
+            if let Some(limit) = limit {
+                check_size(&declared, bytes.len() as u64, limit)?;
+            }
+        }
+
+        // The body may turn out to be something else than declared
+        let mime = content::detect_mime(content_type, &bytes);
+        if mime != declared {
+            check_allowed(policy, &mime)?;
+            if let Some(limit) = policy.max_size(&mime) {
+                check_size(&mime, bytes.len() as u64, limit)?;
+            }
+        }
+        let (body, charset) = if content::is_text(&mime) {
+            let (body, encoding, source) = charset::decode(&bytes, content_type);
+            (body, Some((encoding, source)))
+        } else {
+            (String::new(), None)
+        };
+
+        let page = FetchedPage {
+            url: url.to_string(),
+            final_url,
+            status,
+            headers,
+            mime,
+            bytes,
+            body,
+            charset,
+            fetched_at,
+            elapsed: started.elapsed(),
+        };
+        page.log();
+        Ok(page)
+    }
+
+    pub fn is_html(&self) -> bool {
+        content::is_html(&self.mime)
+    }
+
+    pub fn is_text(&self) -> bool {
+        content::is_text(&self.mime)
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    fn log(&self) {
+        let redirected_to = (self.final_url != self.url).then_some(self.final_url.as_str());
+        info!(
+            status = self.status,
+            elapsed_ms = self.elapsed.as_millis() as u64,
+            mime = %self.mime,
+            charset = self.charset.map(|(encoding, _)| encoding.name()),
+            bytes = self.bytes.len(),
+            redirected_to,
+            "Fetched"
+        );
+    }
+}
+
+fn check_allowed(policy: &ContentPolicy, mime: &str) -> Result<(), String> {
+    if policy.allows(mime) {
+        Ok(())
+    } else {
+        Err(format!("content type {} is not allowed", mime))
+    }
+}
+
+fn check_size(mime: &str, size: u64, limit: u64) -> Result<(), String> {
+    if size > limit {
+        Err(format!(
+            "{} body of {} bytes exceeds the {} byte limit",
+            mime, size, limit
+        ))
+    } else {
+        Ok(())
+    }
+}
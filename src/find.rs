@@ -1,87 +1,295 @@
 use crate::config::ScraperConfig;
+use crate::feed;
+use crate::fetch::{FetchedPage, Fetcher};
+use crate::graph::LinkEdge;
+use crate::json;
+use crate::metadata::MetadataExtractor;
+use crate::output::{self, OutputSink};
+use crate::storage::CrawlDb;
+use crate::table::{Table, TableExtractor};
 use chrono::prelude::*;
 use regex::Regex;
 use scraper::{Html, Selector};
 use serde::{Deserialize, Serialize};
-use serde_json;
+use serde_json::Value;
+use std::collections::HashSet;
 use std::fs;
-use std::fs::OpenOptions;
-use std::io::prelude::*;
+use std::sync::{Arc, Mutex};
+use tracing::{debug, info, trace, warn};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ScrapedData {
     content: String,
+    #[serde(flatten)]
+    fields: serde_json::Map<String, Value>,
 }
 
-pub fn save_scraped_data_as_json(data: &ScrapedData, file_name: &str) -> std::io::Result<()> {
-    let json_data = serde_json::to_string(&data).unwrap();
+impl ScrapedData {
+    pub fn new(content: String) -> Self {
+        ScrapedData {
+            content,
+            fields: serde_json::Map::new(),
+        }
+    }
+}
 
+pub fn save_table_as_csv(
+    table: &Table,
+    url: &str,
+    index: usize,
+    timestamp: &str,
+) -> std::io::Result<()> {
     // Create the "Results" directory if it doesn't exist
     fs::create_dir_all("Results")?;
 
-    // Get the current timestamp and format it
-    let timestamp = Local::now().format("%Y%m%d%H%M%S");
+    // One file per table, named after the run and the page it came from
+    let file_path = format!(
+        "Results/{}_{}_table_{}.csv",
+        timestamp,
+        file_name_part(url),
+        index + 1
+    );
 
-    // Create the file path with the timestamp and the folder
-    let file_path = format!("Results/{}_{}", timestamp, file_name);
+    let mut writer = csv::Writer::from_path(file_path)?;
+    writer.write_record(&table.headers)?;
+    for row in &table.rows {
+        writer.write_record(row)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
 
-    // Open the file with the new file path
-    let mut file = OpenOptions::new()
-        .write(true)
-        .append(true)
-        .create(true)
-        .open(file_path)?;
+/// A URL made safe to use in a file name: anything but ASCII letters, digits,
+/// `-` and `.` becomes `_`, and long URLs are cut short.
+fn file_name_part(url: &str) -> String {
+    const MAX_LENGTH: usize = 100;
+    let url = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+        .unwrap_or(url);
+    url.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(MAX_LENGTH)
+        .collect()
+}
 
-    writeln!(file, "{}", json_data)?;
-    Ok(())
+#[derive(Serialize, Debug)]
+pub struct TableRow {
+    url: String,
+    table: usize,
+    row: serde_json::Map<String, Value>,
+}
+
+/// The text a browser would render: every text node outside `<script>`,
+/// `<style>` and `<noscript>`, with whitespace collapsed.
+pub fn visible_text(html: &str) -> String {
+    let document = Html::parse_document(html);
+
+    document
+        .root_element()
+        .descendants()
+        .filter_map(|node| {
+            let text = node.value().as_text()?;
+            let parent = node.parent().and_then(scraper::ElementRef::wrap)?;
+            match parent.value().name() {
+                "script" | "style" | "noscript" | "template" => None,
+                _ => Some(&**text),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
 }
 
 pub struct Scraper {
     config: ScraperConfig,
+    // Feed entry ids already saved, so repeated runs only emit new entries
+    seen_feed_entries: Mutex<HashSet<String>>,
+    // Opened on the first record of a run and closed by `close_output`
+    output: tokio::sync::Mutex<Option<Box<dyn OutputSink>>>,
+    storage: Option<Arc<CrawlDb>>,
+    fetcher: Arc<Fetcher>,
+    // Names the files written during the current run; set by `start_run`
+    run_timestamp: Mutex<String>,
 }
 
 impl Scraper {
-    pub fn new(config: ScraperConfig) -> Self {
-        Scraper { config }
+    pub fn new(
+        config: ScraperConfig,
+        fetcher: Arc<Fetcher>,
+        storage: Option<Arc<CrawlDb>>,
+    ) -> Self {
+        Scraper {
+            config,
+            seen_feed_entries: Mutex::new(HashSet::new()),
+            output: tokio::sync::Mutex::new(None),
+            storage,
+            fetcher,
+            run_timestamp: Mutex::new(Local::now().format("%Y%m%d%H%M%S").to_string()),
+        }
+    }
+
+    /// Starts a new run, so its result files get a timestamp of their own.
+    pub fn start_run(&self) {
+        *self.run_timestamp.lock().unwrap() = Local::now().format("%Y%m%d%H%M%S").to_string();
+    }
+
+    pub fn run_timestamp(&self) -> String {
+        self.run_timestamp.lock().unwrap().clone()
+    }
+
+    /// Fetches a page to extract from, recording it in the crawl database.
+    /// Error pages are recorded but not returned, so they never get scraped.
+    pub async fn fetch(&self, url: &str) -> Result<FetchedPage, Box<dyn std::error::Error>> {
+        let page = self.fetcher.fetch(url).await?;
+        self.record_page(&page, 0, None)?;
+
+        if !page.is_success() {
+            return Err(format!("{} returned HTTP {}, not scraping it", url, page.status).into());
+        }
+        Ok(page)
     }
+
+    /// Records a fetched page in the crawl database, if one is configured.
+    pub fn record_page(
+        &self,
+        page: &FetchedPage,
+        depth: usize,
+        referrer: Option<&str>,
+    ) -> std::io::Result<()> {
+        match &self.storage {
+            Some(storage) => storage.record_page(page, depth, referrer),
+            None => Ok(()),
+        }
+    }
+
+    /// Records a link edge in the crawl database, if one is configured.
+    pub fn record_link(&self, link: &LinkEdge) -> std::io::Result<()> {
+        match &self.storage {
+            Some(storage) => storage.record_link(link),
+            None => Ok(()),
+        }
+    }
+
+    /// Finishes the current run's output; the next record opens a new one.
+    pub async fn close_output(&self) -> std::io::Result<()> {
+        if let Some(mut sink) = self.output.lock().await.take() {
+            sink.finish().await?;
+        }
+        Ok(())
+    }
+
+    /// Runs the field processors over a record and saves it. Coercion failures
+    /// are reported on stderr and kept in the record under `_errors`.
+    async fn save_record<T: Serialize>(&self, page_url: &str, record: &T) -> std::io::Result<()> {
+        let mut value = serde_json::to_value(record).map_err(std::io::Error::other)?;
+
+        if let Value::Object(fields) = &mut value {
+            if !self.config.processors.is_empty() {
+                let errors = self.config.processors.apply(fields);
+                for (field, error) in &errors {
+                    warn!(
+                        url = %page_url,
+                        field = %field,
+                        error = error.as_str().unwrap_or_default(),
+                        "Field processing failed"
+                    );
+                }
+                if !errors.is_empty() {
+                    fields.insert("_errors".to_string(), Value::Object(errors));
+                }
+            }
+        }
+
+        if let Some(storage) = &self.storage {
+            storage.record_item(page_url, &value)?;
+        }
+        self.fetcher.stats().record_item();
+
+        let mut output = self.output.lock().await;
+        let sink = match &mut *output {
+            Some(sink) => sink,
+            None => output.insert(output::open_sink(
+                &self.config,
+                &self.fetcher,
+                &self.run_timestamp(),
+            )?),
+        };
+        sink.write(&value).await
+    }
+
     pub async fn scrape_data(
         &self,
+        page: &FetchedPage,
         selectors: &[String],
     ) -> Result<(), Box<dyn std::error::Error>> {
-        if self.config.use_regex {
-            println!("Scraping data with regex patterns.");
-            self.scrape_data_with_regex(selectors).await?;
-        } else {
-            let url = &self.config.base_url;
-            let response = reqwest::get(url).await?;
-            println!("Page fetched successfully.");
-            let html = response.text().await?;
-            let parsed_html = Html::parse_document(&html);
-
-            for selector in selectors {
-                let scraper_selector = match Selector::parse(selector) {
-                    Ok(selector) => selector,
-                    Err(_err) => {
-                        eprintln!("Invalid selector");
-                        continue;
-                    }
-                };
+        let is_json = self.config.force_json || json::is_json_content_type(&page.mime);
+
+        if is_json {
+            // One bad body shouldn't end a crawl, so the page is skipped
+            match serde_json::from_str::<Value>(&page.body) {
+                Ok(document) => self.scrape_json_document(page, &document).await?,
+                Err(err) => warn!(url = %page.url, error = %err, "Skipping page with invalid JSON"),
+            }
+            return Ok(());
+        }
+
+        let parsed_html = Html::parse_document(&page.body);
 
-                println!("\nScraping data for selector: {}", selector);
-                for element in parsed_html.select(&scraper_selector) {
-                    let content = element.text().collect::<Vec<_>>().join(" ");
-                    let trimmed_content = content.trim().to_string();
-                    let scraped_data = ScrapedData {
-                        content: trimmed_content.clone(),
-                    };
-                    save_scraped_data_as_json(&scraped_data, "output.json")
-                        .expect("Failed to save data as JSON");
-
-                    println!("{}", element.inner_html().trim());
-                    println!("{}", content);
-                    println!("{{\"{}\"}}", content);
-                    println!("{}", content);
+        for selector in selectors {
+            let scraper_selector = match Selector::parse(selector) {
+                Ok(selector) => selector,
+                Err(err) => {
+                    warn!(selector = %selector, error = %err, "Invalid CSS selector");
+                    continue;
                 }
+            };
+
+            debug!(selector = %selector, "Scraping data for selector");
+            for element in parsed_html.select(&scraper_selector) {
+                let content = element.text().collect::<Vec<_>>().join(" ");
+                let trimmed_content = content.trim().to_string();
+                let scraped_data = ScrapedData::new(trimmed_content.clone());
+                self.save_record(&page.url, &scraped_data).await?;
+
+                trace!(selector = %selector, content = %trimmed_content, "Scraped");
+            }
+        }
+
+        if self.config.has_json_queries() {
+            for document in json::extract_embedded_json(&parsed_html, &self.config.json_script) {
+                self.scrape_json_document(page, &document).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Applies the configured JSONPath/JMESPath queries to a parsed JSON document
+    /// and saves every selected value as a record.
+    async fn scrape_json_document(
+        &self,
+        page: &FetchedPage,
+        document: &Value,
+    ) -> std::io::Result<()> {
+        let queries = json::compile_queries(&self.config.json_paths, &self.config.jmespaths);
+
+        for query in &queries {
+            debug!(query = query.expression(), "Scraping data for JSON query");
+            for value in query.select(document) {
+                let content = json::value_to_content(&value);
+                let scraped_data = ScrapedData::new(content.clone());
+                self.save_record(&page.url, &scraped_data).await?;
+
+                trace!(query = query.expression(), content = %content, "Scraped");
             }
         }
 
@@ -90,37 +298,197 @@ impl Scraper {
 
     pub async fn scrape_data_with_regex(
         &self,
+        page: &FetchedPage,
         patterns: &[String],
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let url = &self.config.base_url;
-        let response = reqwest::get(url).await?;
-        println!("Page fetched successfully.");
-        let html = response.text().await?;
+        let haystack = if self.config.regex_target == "text" {
+            visible_text(&page.body)
+        } else {
+            page.body.clone()
+        };
 
         for pattern in patterns {
             let regex = match Regex::new(pattern) {
                 Ok(regex) => regex,
-                Err(_err) => {
-                    eprintln!("Invalid regex pattern");
+                Err(err) => {
+                    warn!(pattern = %pattern, error = %err, "Invalid regex pattern");
                     continue;
                 }
             };
 
-            println!("\nScraping data for regex pattern: {}", pattern);
-            for capture in regex.captures_iter(&html) {
+            debug!(pattern = %pattern, "Scraping data for regex pattern");
+            for capture in regex.captures_iter(&haystack) {
                 let content = capture.get(0).map_or("", |m| m.as_str()).to_string();
-                let scraped_data = ScrapedData {
-                    content: content.clone(),
+                let mut scraped_data = ScrapedData::new(content.clone());
+                scraped_data.fields = capture_fields(&regex, &capture);
+                self.save_record(&page.url, &scraped_data).await?;
+
+                trace!(
+                    pattern = %pattern,
+                    content = %content,
+                    fields = %serde_json::to_string(&scraped_data.fields).unwrap_or_default(),
+                    "Scraped"
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn scrape_feed(&self, page: &FetchedPage) -> Result<(), Box<dyn std::error::Error>> {
+        if self.config.feed {
+            let entries = feed::parse_feed(page.body.as_bytes())?;
+            let total = entries.len();
+            let new_entries: Vec<_> = {
+                let mut seen = self.seen_feed_entries.lock().unwrap();
+                entries
+                    .into_iter()
+                    .filter(|entry| seen.insert(entry.id.clone()))
+                    .collect()
+            };
+
+            info!(total, new = new_entries.len(), "Feed entries read");
+            for entry in &new_entries {
+                self.save_record(&page.url, entry).await?;
+
+                debug!(
+                    title = entry.title.as_deref().unwrap_or("<untitled>"),
+                    link = entry.link.as_deref().unwrap_or(""),
+                    "New feed entry"
+                );
+            }
+        }
+
+        if !self.config.xpaths.is_empty() {
+            for expression in &self.config.xpaths {
+                let values = match feed::evaluate_xpath(&page.body, expression) {
+                    Ok(values) => values,
+                    Err(err) => {
+                        warn!(xpath = %expression, error = %err, "XPath evaluation failed");
+                        continue;
+                    }
                 };
-                save_scraped_data_as_json(&scraped_data, "output.json")
-                    .expect("Failed to save data as JSON");
 
-                println!("{}", content);
-                println!("{{\"{}\"}}", content);
-                println!("{}", content);
+                debug!(xpath = %expression, "Scraping data for XPath");
+                for content in values {
+                    let scraped_data = ScrapedData::new(content.clone());
+                    self.save_record(&page.url, &scraped_data).await?;
+
+                    trace!(xpath = %expression, content = %content, "Scraped");
+                }
             }
         }
 
         Ok(())
     }
+
+    pub async fn scrape_metadata(
+        &self,
+        page: &FetchedPage,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let records = MetadataExtractor::new().extract(&page.url, &page.body);
+        debug!(url = %page.url, records = records.len(), "Structured metadata found");
+        for record in &records {
+            self.save_record(&page.url, record).await?;
+
+            trace!(
+                format = %record.format,
+                item_type = record.item_type.as_deref().unwrap_or("-"),
+                "Metadata record"
+            );
+        }
+
+        Ok(())
+    }
+
+    pub async fn scrape_tables(
+        &self,
+        page: &FetchedPage,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let selector = match &self.config.table_selector {
+            Some(selector) => selector,
+            None => return Ok(()),
+        };
+
+        let tables = TableExtractor::new().extract_tables(&page.body, selector)?;
+        debug!(selector = %selector, tables = tables.len(), "Tables found");
+
+        for (index, table) in tables.iter().enumerate() {
+            trace!(
+                table = index + 1,
+                columns = table.headers.len(),
+                rows = table.rows.len(),
+                "Table"
+            );
+
+            if self.config.table_format == "csv" {
+                save_table_as_csv(table, &page.url, index, &self.run_timestamp())?;
+                continue;
+            }
+
+            for row in table.row_objects() {
+                let table_row = TableRow {
+                    url: page.url.clone(),
+                    table: index + 1,
+                    row,
+                };
+                self.save_record(&page.url, &table_row).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Named groups become fields under their name, unnamed ones as group_<n>. A
+/// group named `content` is stored as `group_content` so it can't shadow the
+/// whole match.
+fn capture_fields(regex: &Regex, capture: &regex::Captures) -> serde_json::Map<String, Value> {
+    regex
+        .capture_names()
+        .enumerate()
+        .skip(1)
+        .map(|(index, name)| {
+            let field = match name {
+                Some("content") => "group_content".to_string(),
+                Some(name) => name.to_string(),
+                None => format!("group_{}", index),
+            };
+            let value = capture
+                .get(index)
+                .map_or(Value::Null, |m| Value::String(m.as_str().to_string()));
+            (field, value)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_name_part_is_safe_and_bounded() {
+        assert_eq!(
+            file_name_part("https://example.com/a/b?q=1&x=ä"),
+            "example.com_a_b_q_1_x__"
+        );
+        assert_eq!(
+            file_name_part(&format!("http://{}", "a".repeat(500))).len(),
+            100
+        );
+    }
+
+    #[test]
+    fn capture_fields_keep_the_whole_match_as_content() {
+        let regex = Regex::new(r"(?P<content>\w+)=(?P<value>\d+)(x)?").unwrap();
+        let capture = regex.captures("price=3").unwrap();
+        let mut record = ScrapedData::new(capture[0].to_string());
+        record.fields = capture_fields(&regex, &capture);
+
+        let json = serde_json::to_value(&record).unwrap();
+        assert_eq!(json["content"], "price=3");
+        assert_eq!(json["group_content"], "price");
+        assert_eq!(json["value"], "3");
+        assert_eq!(json["group_3"], Value::Null);
+    }
 }
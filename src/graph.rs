@@ -0,0 +1,449 @@
+use reqwest::Url;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    fs::{self, File},
+    io::{self, prelude::*},
+};
+use tracing::{info, warn};
+
+use crate::{feed, fetch::Fetcher};
+
+/// A link found on a crawled page.
+#[derive(Clone, Debug)]
+pub struct LinkEdge {
+    pub source: String,
+    pub target: String,
+    pub anchor_text: String,
+    pub rel: Option<String>,
+}
+
+/// Computed link metrics for one page of the graph.
+#[derive(Debug)]
+pub struct PageMetrics {
+    pub in_degree: usize,
+    pub out_degree: usize,
+    // Fewest clicks from the start page; None when no crawled link leads there
+    pub depth: Option<usize>,
+    pub in_sitemap: bool,
+    // Listed in the sitemap but no crawled page links to it
+    pub orphan: bool,
+}
+
+/// The pages visited by a crawl and the links between them.
+#[derive(Default)]
+pub struct LinkGraph {
+    start: String,
+    pages: BTreeSet<String>,
+    edges: Vec<LinkEdge>,
+}
+
+impl LinkGraph {
+    pub fn new(start: &str) -> Self {
+        LinkGraph {
+            start: normalize_url(start),
+            ..Default::default()
+        }
+    }
+
+    pub fn add_page(&mut self, url: &str) {
+        self.pages.insert(normalize_url(url));
+    }
+
+    pub fn add_edge(&mut self, mut edge: LinkEdge) {
+        edge.source = normalize_url(&edge.source);
+        edge.target = normalize_url(&edge.target);
+        self.edges.push(edge);
+    }
+
+    /// Metrics for every crawled page, link target and sitemap URL, keyed by URL.
+    pub fn metrics(&self, sitemap: &BTreeSet<String>) -> BTreeMap<String, PageMetrics> {
+        let mut outgoing: HashMap<&str, BTreeSet<&str>> = HashMap::new();
+        let mut incoming: HashMap<&str, BTreeSet<&str>> = HashMap::new();
+        for edge in &self.edges {
+            if edge.source == edge.target {
+                continue;
+            }
+            outgoing
+                .entry(&edge.source)
+                .or_default()
+                .insert(&edge.target);
+            incoming
+                .entry(&edge.target)
+                .or_default()
+                .insert(&edge.source);
+        }
+
+        // Breadth-first from the start page, so depth is the shortest click path
+        let mut depths: HashMap<&str, usize> = HashMap::new();
+        let mut queue = VecDeque::from([(self.start.as_str(), 0)]);
+        while let Some((url, depth)) = queue.pop_front() {
+            if depths.contains_key(url) {
+                continue;
+            }
+            depths.insert(url, depth);
+            for target in outgoing.get(url).into_iter().flatten() {
+                queue.push_back((target, depth + 1));
+            }
+        }
+
+        let urls = self
+            .pages
+            .iter()
+            .chain(self.edges.iter().map(|edge| &edge.target))
+            .chain(sitemap.iter());
+
+        urls.map(|url| {
+            let in_degree = incoming.get(url.as_str()).map_or(0, BTreeSet::len);
+            let in_sitemap = sitemap.contains(url);
+            let metrics = PageMetrics {
+                in_degree,
+                out_degree: outgoing.get(url.as_str()).map_or(0, BTreeSet::len),
+                depth: depths.get(url.as_str()).copied(),
+                in_sitemap,
+                orphan: in_sitemap && in_degree == 0 && *url != self.start,
+            };
+            (url.clone(), metrics)
+        })
+        .collect()
+    }
+
+    /// Writes the graph in each requested format plus a per-page metrics CSV,
+    /// all under `Results/<timestamp>_link_graph.*`.
+    pub fn export(
+        &self,
+        formats: &[String],
+        sitemap: &BTreeSet<String>,
+        timestamp: &str,
+    ) -> io::Result<()> {
+        fs::create_dir_all("Results")?;
+        let metrics = self.metrics(sitemap);
+
+        for format in formats {
+            let file_path = match format.as_str() {
+                "graphml" => format!("Results/{}_link_graph.graphml", timestamp),
+                "dot" => format!("Results/{}_link_graph.dot", timestamp),
+                _ => format!("Results/{}_link_graph_edges.csv", timestamp),
+            };
+            match format.as_str() {
+                "graphml" => self.write_graphml(&file_path, &metrics)?,
+                "dot" => self.write_dot(&file_path, &metrics)?,
+                _ => self.write_edges_csv(&file_path)?,
+            }
+            info!(file = %file_path, "Link graph saved");
+        }
+
+        let file_path = format!("Results/{}_link_metrics.csv", timestamp);
+        write_metrics_csv(&file_path, &metrics)?;
+        let orphans = metrics.values().filter(|page| page.orphan).count();
+        info!(
+            pages = metrics.len(),
+            orphans,
+            file = %file_path,
+            "Link metrics saved"
+        );
+
+        Ok(())
+    }
+
+    fn write_graphml(
+        &self,
+        file_path: &str,
+        metrics: &BTreeMap<String, PageMetrics>,
+    ) -> io::Result<()> {
+        let mut file = io::BufWriter::new(File::create(file_path)?);
+        writeln!(file, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            file,
+            r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+        )?;
+        for (id, kind, name) in [
+            ("in_degree", "node", "int"),
+            ("out_degree", "node", "int"),
+            ("depth", "node", "int"),
+            ("in_sitemap", "node", "boolean"),
+            ("orphan", "node", "boolean"),
+            ("anchor_text", "edge", "string"),
+            ("rel", "edge", "string"),
+        ] {
+            writeln!(
+                file,
+                r#"  <key id="{0}" for="{1}" attr.name="{0}" attr.type="{2}"/>"#,
+                id, kind, name
+            )?;
+        }
+        writeln!(file, r#"  <graph id="links" edgedefault="directed">"#)?;
+
+        for (url, page) in metrics {
+            writeln!(file, r#"    <node id="{}">"#, xml_escape(url))?;
+            writeln!(
+                file,
+                r#"      <data key="in_degree">{}</data>"#,
+                page.in_degree
+            )?;
+            writeln!(
+                file,
+                r#"      <data key="out_degree">{}</data>"#,
+                page.out_degree
+            )?;
+            if let Some(depth) = page.depth {
+                writeln!(file, r#"      <data key="depth">{}</data>"#, depth)?;
+            }
+            writeln!(
+                file,
+                r#"      <data key="in_sitemap">{}</data>"#,
+                page.in_sitemap
+            )?;
+            writeln!(file, r#"      <data key="orphan">{}</data>"#, page.orphan)?;
+            writeln!(file, "    </node>")?;
+        }
+        for edge in &self.edges {
+            writeln!(
+                file,
+                r#"    <edge source="{}" target="{}">"#,
+                xml_escape(&edge.source),
+                xml_escape(&edge.target)
+            )?;
+            writeln!(
+                file,
+                r#"      <data key="anchor_text">{}</data>"#,
+                xml_escape(&edge.anchor_text)
+            )?;
+            if let Some(rel) = &edge.rel {
+                writeln!(file, r#"      <data key="rel">{}</data>"#, xml_escape(rel))?;
+            }
+            writeln!(file, "    </edge>")?;
+        }
+
+        writeln!(file, "  </graph>")?;
+        writeln!(file, "</graphml>")?;
+        file.flush()
+    }
+
+    fn write_dot(
+        &self,
+        file_path: &str,
+        metrics: &BTreeMap<String, PageMetrics>,
+    ) -> io::Result<()> {
+        let mut file = io::BufWriter::new(File::create(file_path)?);
+        writeln!(file, "digraph links {{")?;
+
+        for (url, page) in metrics {
+            let depth = page
+                .depth
+                .map_or("-".to_string(), |depth| depth.to_string());
+            write!(
+                file,
+                "  \"{}\" [in_degree={}, out_degree={}, depth=\"{}\", in_sitemap={}",
+                dot_escape(url),
+                page.in_degree,
+                page.out_degree,
+                depth,
+                page.in_sitemap
+            )?;
+            if page.orphan {
+                write!(file, ", orphan=true, color=red")?;
+            }
+            writeln!(file, "];")?;
+        }
+        for edge in &self.edges {
+            write!(
+                file,
+                "  \"{}\" -> \"{}\" [label=\"{}\"",
+                dot_escape(&edge.source),
+                dot_escape(&edge.target),
+                dot_escape(&edge.anchor_text)
+            )?;
+            if let Some(rel) = &edge.rel {
+                write!(file, ", rel=\"{}\"", dot_escape(rel))?;
+            }
+            writeln!(file, "];")?;
+        }
+
+        writeln!(file, "}}")?;
+        file.flush()
+    }
+
+    fn write_edges_csv(&self, file_path: &str) -> io::Result<()> {
+        let mut writer = csv::Writer::from_path(file_path)?;
+        writer.write_record(["source", "target", "anchor_text", "rel"])?;
+        for edge in &self.edges {
+            writer.write_record([
+                edge.source.as_str(),
+                edge.target.as_str(),
+                edge.anchor_text.as_str(),
+                edge.rel.as_deref().unwrap_or(""),
+            ])?;
+        }
+        writer.flush()
+    }
+}
+
+fn write_metrics_csv(file_path: &str, metrics: &BTreeMap<String, PageMetrics>) -> io::Result<()> {
+    let mut writer = csv::Writer::from_path(file_path)?;
+    writer.write_record([
+        "url",
+        "in_degree",
+        "out_degree",
+        "depth",
+        "in_sitemap",
+        "orphan",
+    ])?;
+    for (url, page) in metrics {
+        writer.write_record([
+            url.clone(),
+            page.in_degree.to_string(),
+            page.out_degree.to_string(),
+            page.depth
+                .map(|depth| depth.to_string())
+                .unwrap_or_default(),
+            page.in_sitemap.to_string(),
+            page.orphan.to_string(),
+        ])?;
+    }
+    writer.flush()
+}
+
+/// Fetches the page URLs listed in a sitemap, following a sitemap index one
+/// level down. Missing or unparsable sitemaps yield no URLs.
+pub async fn fetch_sitemap(fetcher: &Fetcher, url: &str) -> BTreeSet<String> {
+    let mut urls = BTreeSet::new();
+    let mut sitemaps = vec![url.to_string()];
+    let mut nested = true;
+
+    while let Some(sitemap_url) = sitemaps.pop() {
+        let xml = match fetcher.fetch(&sitemap_url).await {
+            Ok(page) if page.is_success() => page.body,
+            Ok(page) => {
+                warn!(sitemap = %sitemap_url, status = page.status, "Could not read sitemap");
+                continue;
+            }
+            Err(err) => {
+                warn!(sitemap = %sitemap_url, error = %err, "Could not read sitemap");
+                continue;
+            }
+        };
+
+        if nested {
+            if let Ok(children) =
+                feed::evaluate_xpath(&xml, "//*[local-name()='sitemap']/*[local-name()='loc']")
+            {
+                sitemaps.extend(children);
+            }
+        }
+        match feed::evaluate_xpath(&xml, "//*[local-name()='url']/*[local-name()='loc']") {
+            Ok(locations) => urls.extend(locations.into_iter().map(|loc| normalize_url(&loc))),
+            Err(err) => warn!(sitemap = %sitemap_url, error = %err, "Could not read sitemap"),
+        }
+        nested = false;
+    }
+
+    info!(urls = urls.len(), "Sitemap read");
+    urls
+}
+
+/// Parses and re-serializes a URL without its fragment, so sitemap entries and
+/// links to different anchors of a page all name the same node.
+fn normalize_url(url: &str) -> String {
+    let url = url.trim();
+    match Url::parse(url) {
+        Ok(mut parsed) => {
+            parsed.set_fragment(None);
+            parsed.to_string()
+        }
+        Err(_) => url.split('#').next().unwrap_or_default().to_string(),
+    }
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(start: &str, links: &[(&str, &str)]) -> LinkGraph {
+        let mut graph = LinkGraph::new(start);
+        for (source, target) in links {
+            graph.add_page(source);
+            graph.add_edge(LinkEdge {
+                source: source.to_string(),
+                target: target.to_string(),
+                anchor_text: String::new(),
+                rel: None,
+            });
+        }
+        graph
+    }
+
+    #[test]
+    fn depth_is_the_shortest_click_path() {
+        // d is three clicks away through b and c, but one through the start page
+        let graph = graph(
+            "a",
+            &[("a", "b"), ("b", "c"), ("c", "d"), ("a", "d"), ("d", "a")],
+        );
+        let metrics = graph.metrics(&BTreeSet::new());
+
+        let depths: Vec<(&str, Option<usize>)> = metrics
+            .iter()
+            .map(|(url, page)| (url.as_str(), page.depth))
+            .collect();
+        assert_eq!(
+            depths,
+            [
+                ("a", Some(0)),
+                ("b", Some(1)),
+                ("c", Some(2)),
+                ("d", Some(1))
+            ]
+        );
+        assert_eq!(metrics["a"].in_degree, 1);
+        assert_eq!(metrics["a"].out_degree, 2);
+    }
+
+    #[test]
+    fn unreachable_and_orphan_pages_have_no_depth() {
+        // x links to y but nothing links to x; self-links don't count
+        let graph = graph("a", &[("a", "a"), ("x", "y")]);
+        let sitemap = BTreeSet::from(["a".to_string(), "z".to_string()]);
+        let metrics = graph.metrics(&sitemap);
+
+        assert_eq!(metrics["a"].depth, Some(0));
+        assert_eq!(metrics["a"].in_degree, 0);
+        assert!(!metrics["a"].orphan);
+        assert_eq!(metrics["x"].depth, None);
+        assert_eq!(metrics["y"].depth, None);
+        assert_eq!(metrics["y"].in_degree, 1);
+        assert!(metrics["z"].orphan);
+        assert_eq!(metrics["z"].depth, None);
+    }
+
+    #[test]
+    fn links_to_anchors_point_at_the_page() {
+        let graph = graph(
+            "https://example.com/",
+            &[
+                ("https://example.com/#top", "https://example.com/page#a"),
+                ("https://example.com/", "https://example.com/page#b"),
+                ("https://example.com/page", "https://example.com/#top"),
+            ],
+        );
+        let metrics = graph.metrics(&BTreeSet::new());
+
+        assert_eq!(
+            metrics.keys().collect::<Vec<_>>(),
+            ["https://example.com/", "https://example.com/page"]
+        );
+        assert_eq!(metrics["https://example.com/page"].in_degree, 1);
+        assert_eq!(metrics["https://example.com/page"].depth, Some(1));
+        assert_eq!(metrics["https://example.com/"].in_degree, 1);
+    }
+}
@@ -0,0 +1,115 @@
+use scraper::{Html, Selector};
+use serde_json::Value;
+use serde_json_path::JsonPath;
+use tracing::warn;
+
+/// Script tags that commonly carry page state as JSON (Next.js, Nuxt, etc.).
+pub const DEFAULT_JSON_SCRIPT_SELECTOR: &str =
+    "script#__NEXT_DATA__, script[type='application/json'], script[type='application/ld+json']";
+
+pub enum JsonQuery {
+    Path(String, JsonPath),
+    Jmes(String, jmespath::Expression<'static>),
+}
+
+impl JsonQuery {
+    pub fn parse_json_path(expression: &str) -> Result<JsonQuery, String> {
+        JsonPath::parse(expression)
+            .map(|path| JsonQuery::Path(expression.to_string(), path))
+            .map_err(|err| format!("Invalid JSONPath '{}': {}", expression, err))
+    }
+
+    pub fn parse_jmespath(expression: &str) -> Result<JsonQuery, String> {
+        jmespath::compile(expression)
+            .map(|expr| JsonQuery::Jmes(expression.to_string(), expr))
+            .map_err(|err| format!("Invalid JMESPath '{}': {}", expression, err))
+    }
+
+    pub fn expression(&self) -> &str {
+        match self {
+            JsonQuery::Path(expression, _) | JsonQuery::Jmes(expression, _) => expression,
+        }
+    }
+
+    /// Runs the query against a document. JSONPath yields one value per matched
+    /// node; a JMESPath array result is flattened so each element becomes a record.
+    pub fn select(&self, document: &Value) -> Vec<Value> {
+        match self {
+            JsonQuery::Path(_, path) => path.query(document).all().into_iter().cloned().collect(),
+            JsonQuery::Jmes(expression, expr) => {
+                let result = match expr.search(document) {
+                    Ok(result) => result,
+                    Err(err) => {
+                        warn!(jmespath = %expression, error = %err, "JMESPath query failed");
+                        return Vec::new();
+                    }
+                };
+                match serde_json::to_value(&*result).unwrap_or(Value::Null) {
+                    Value::Null => Vec::new(),
+                    Value::Array(values) => values,
+                    value => vec![value],
+                }
+            }
+        }
+    }
+}
+
+/// Compiles the JSONPath and JMESPath expressions given on the command line,
+/// skipping (and reporting) the ones that fail to parse.
+pub fn compile_queries(json_paths: &[String], jmespaths: &[String]) -> Vec<JsonQuery> {
+    let paths = json_paths
+        .iter()
+        .map(|expression| JsonQuery::parse_json_path(expression.trim()));
+    let jmes = jmespaths
+        .iter()
+        .map(|expression| JsonQuery::parse_jmespath(expression.trim()));
+
+    paths
+        .chain(jmes)
+        .filter_map(|query| match query {
+            Ok(query) => Some(query),
+            Err(err) => {
+                warn!(error = %err, "Skipping JSON query");
+                None
+            }
+        })
+        .collect()
+}
+
+pub fn is_json_content_type(content_type: &str) -> bool {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+    mime == "application/json" || mime.ends_with("+json") || mime == "text/json"
+}
+
+/// Parses every JSON document embedded in `<script>` tags matching `selector`.
+pub fn extract_embedded_json(document: &Html, selector: &str) -> Vec<Value> {
+    let script_selector = match Selector::parse(selector) {
+        Ok(selector) => selector,
+        Err(err) => {
+            warn!(selector = %selector, error = %err, "Invalid JSON script selector");
+            return Vec::new();
+        }
+    };
+
+    document
+        .select(&script_selector)
+        .filter_map(|script| {
+            let text = script.text().collect::<String>();
+            serde_json::from_str(text.trim()).ok()
+        })
+        .collect()
+}
+
+/// Renders a selected value as record content: strings are kept verbatim,
+/// everything else is serialized back to compact JSON.
+pub fn value_to_content(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
@@ -0,0 +1,269 @@
+use reqwest::{header::LOCATION, Method, StatusCode, Url};
+use serde::Serialize;
+use std::{
+    collections::BTreeSet,
+    fs::{self, File},
+    io,
+    time::Instant,
+};
+use tracing::warn;
+
+use crate::fetch::Fetcher;
+
+const MAX_REDIRECTS: usize = 10;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Ok,
+    Redirected,
+    Broken,
+    RedirectLoop,
+    TooManyRedirects,
+    Timeout,
+    Error,
+}
+
+impl Outcome {
+    /// Whether the link fails the check. Redirects that end on a working page
+    /// are reported but do not count as broken.
+    pub fn is_broken(self) -> bool {
+        !matches!(self, Outcome::Ok | Outcome::Redirected)
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Outcome::Ok => "ok",
+            Outcome::Redirected => "redirected",
+            Outcome::Broken => "broken",
+            Outcome::RedirectLoop => "redirect_loop",
+            Outcome::TooManyRedirects => "too_many_redirects",
+            Outcome::Timeout => "timeout",
+            Outcome::Error => "error",
+        }
+    }
+}
+
+/// The result of requesting one URL, following redirects hop by hop.
+pub struct Check {
+    pub outcome: Outcome,
+    // Final status code, if any response was received
+    pub status: Option<u16>,
+    // Every URL visited after the first one
+    pub redirects: Vec<String>,
+    pub error: Option<String>,
+    // The page that was finally reached, for internal pages that get crawled
+    pub final_url: String,
+    pub response: Option<reqwest::Response>,
+}
+
+/// Requests `url` without automatic redirects so the chain, loops and the
+/// final status can be recorded.
+pub async fn check_url(fetcher: &Fetcher, url: &str, method: Method) -> Check {
+    fetcher.stats().record_page();
+    let mut current = url.to_string();
+    let mut redirects = Vec::new();
+    let mut visited = BTreeSet::from([current.clone()]);
+
+    loop {
+        let mut response = match send(fetcher, &current, method.clone()).await {
+            Ok(response) => response,
+            Err(err) => {
+                let outcome = if err
+                    .downcast_ref::<reqwest::Error>()
+                    .is_some_and(|err| err.is_timeout())
+                {
+                    Outcome::Timeout
+                } else {
+                    Outcome::Error
+                };
+                return Check {
+                    outcome,
+                    status: None,
+                    redirects,
+                    error: Some(error_chain(err.as_ref())),
+                    final_url: current,
+                    response: None,
+                };
+            }
+        };
+        // Some servers reject HEAD; fall back to GET before calling the link broken
+        if method == Method::HEAD
+            && matches!(
+                response.status(),
+                StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED
+            )
+        {
+            if let Ok(retry) = send(fetcher, &current, Method::GET).await {
+                response = retry;
+            }
+        }
+
+        let status = response.status();
+        let location = response
+            .headers()
+            .get(LOCATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|location| Url::parse(&current).ok()?.join(location).ok());
+
+        let next = match (status.is_redirection(), location) {
+            (true, Some(next)) => next.to_string(),
+            _ => {
+                let outcome = if status.is_client_error() || status.is_server_error() {
+                    Outcome::Broken
+                } else if redirects.is_empty() {
+                    Outcome::Ok
+                } else {
+                    Outcome::Redirected
+                };
+                return Check {
+                    outcome,
+                    status: Some(status.as_u16()),
+                    redirects,
+                    error: None,
+                    final_url: current,
+                    response: Some(response),
+                };
+            }
+        };
+
+        redirects.push(next.clone());
+        let outcome = if !visited.insert(next.clone()) {
+            Outcome::RedirectLoop
+        } else if redirects.len() > MAX_REDIRECTS {
+            Outcome::TooManyRedirects
+        } else {
+            current = next;
+            continue;
+        };
+        return Check {
+            outcome,
+            status: Some(status.as_u16()),
+            redirects,
+            error: None,
+            final_url: next,
+            response: None,
+        };
+    }
+}
+
+/// Sends one request on the link check client for `url`'s host and proxy.
+async fn send(
+    fetcher: &Fetcher,
+    url: &str,
+    method: Method,
+) -> Result<reqwest::Response, Box<dyn std::error::Error>> {
+    let client = fetcher.link_check_client(url)?;
+    let started = Instant::now();
+    match fetcher.request(&client, method, url).send().await {
+        Ok(response) => {
+            fetcher
+                .stats()
+                .record_response(url, response.status().as_u16(), 0, started.elapsed());
+            Ok(response)
+        }
+        Err(err) => {
+            fetcher.stats().record_error(&err);
+            Err(err.into())
+        }
+    }
+}
+
+/// One checked link, together with the pages that reference it.
+#[derive(Serialize, Debug)]
+pub struct LinkResult {
+    pub url: String,
+    pub outcome: Outcome,
+    pub status: Option<u16>,
+    pub redirects: Vec<String>,
+    pub error: Option<String>,
+    pub external: bool,
+    pub referrers: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct LinkReport {
+    pub checked: usize,
+    pub broken: usize,
+    pub redirected: usize,
+    // Only links that are broken or redirected; working links are just counted
+    pub links: Vec<LinkResult>,
+}
+
+impl LinkReport {
+    pub fn add(&mut self, result: LinkResult) {
+        self.checked += 1;
+        if result.outcome.is_broken() {
+            self.broken += 1;
+            warn!(
+                url = %result.url,
+                problem = %describe(&result),
+                referrers = result.referrers.len(),
+                "Broken link"
+            );
+        } else if result.outcome == Outcome::Redirected {
+            self.redirected += 1;
+        }
+        if result.outcome != Outcome::Ok {
+            self.links.push(result);
+        }
+    }
+
+    /// Saves the report as `Results/<timestamp>_link_report.<json|csv>`.
+    pub fn save(&self, format: &str, timestamp: &str) -> io::Result<String> {
+        fs::create_dir_all("Results")?;
+        let file_path = format!("Results/{}_link_report.{}", timestamp, format);
+
+        if format == "csv" {
+            let mut writer = csv::Writer::from_path(&file_path)?;
+            writer.write_record([
+                "url",
+                "outcome",
+                "status",
+                "redirects",
+                "error",
+                "external",
+                "referrers",
+            ])?;
+            for link in &self.links {
+                writer.write_record([
+                    link.url.clone(),
+                    link.outcome.as_str().to_string(),
+                    link.status
+                        .map(|status| status.to_string())
+                        .unwrap_or_default(),
+                    link.redirects.join(" -> "),
+                    link.error.clone().unwrap_or_default(),
+                    link.external.to_string(),
+                    link.referrers.join(" | "),
+                ])?;
+            }
+            writer.flush()?;
+        } else {
+            serde_json::to_writer_pretty(File::create(&file_path)?, self)?;
+        }
+
+        Ok(file_path)
+    }
+}
+
+/// The error message followed by its causes, since reqwest's own message
+/// rarely says what actually went wrong.
+fn error_chain(err: &dyn std::error::Error) -> String {
+    let mut message = err.to_string();
+    let mut source = err.source();
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    message
+}
+
+fn describe(result: &LinkResult) -> String {
+    match (result.status, &result.error) {
+        (_, Some(error)) => format!("{}: {}", result.outcome.as_str(), error),
+        (Some(status), None) => format!("{} {}", result.outcome.as_str(), status),
+        (None, None) => result.outcome.as_str().to_string(),
+    }
+}
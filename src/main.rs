@@ -1,42 +1,150 @@
+mod auth;
+mod charset;
 mod config;
+mod console;
+mod content;
+mod cookies;
 mod crawler;
+mod feed;
+mod fetch;
 mod find;
+mod graph;
+mod json;
+mod linkcheck;
+mod metadata;
+mod output;
+mod process;
+mod proxy;
 mod selector;
+mod stats;
+mod storage;
+mod table;
+#[cfg(test)]
+mod test_support;
+mod tls;
 
+use colored::*;
 use config::{CliOptions, ScraperConfig};
 use crawler::Crawler;
+use fetch::Fetcher;
 use find::Scraper;
+use rand::seq::SliceRandom;
+use rand::thread_rng;
 use selector::SelectorExtractor;
+use std::sync::Arc;
 use std::time::Duration;
+use storage::CrawlDb;
 use structopt::StructOpt;
 use tokio::time::sleep;
-use colored::*;
-use rand::seq::SliceRandom;
-use rand::thread_rng;
+use tracing::{error, info, Instrument};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    display_welcome_message();
     // Parse CLI options
     let options = CliOptions::from_args();
     // Create a ScraperConfig from CLI options
     let config = ScraperConfig::from_options(options.clone())?;
+    console::init_logging(&config.log)?;
+
+    // Streaming records to stdout: keep the banner out of the record stream
+    if !config.streams_to_stdout() && config.log.verbosity >= 0 {
+        display_welcome_message();
+    }
+    let storage = match &config.crawl_db {
+        Some(path) => Some(Arc::new(CrawlDb::open(path)?)),
+        None => None,
+    };
+    // One pooled HTTP client set for the whole process
+    let fetcher = Arc::new(Fetcher::new(&config)?);
+    // Shared across interval runs so feed mode only reports new entries
+    let scraper = Arc::new(Scraper::new(
+        config.clone(),
+        fetcher.clone(),
+        storage.clone(),
+    ));
 
-    // Run the scraper
+    // Run the scraper, then finish the stats, close this run's output and save
+    // the cookies even when it fails part way, returning the first error.
+    // Returns the number of broken links found by --check-links.
     async fn run_scraper(
         options: &CliOptions,
         config: &ScraperConfig,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+        scraper: &Arc<Scraper>,
+        fetcher: &Arc<Fetcher>,
+        storage: &Option<Arc<CrawlDb>>,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        scraper.start_run();
+        if let Some(storage) = storage {
+            storage.start_run(&config.base_url)?;
+        }
+        if let Some(login) = &config.login {
+            login.run(fetcher).await?;
+        }
+        fetcher
+            .stats()
+            .start(config.max_pages.filter(|_| options.crawl));
+        let mut result = run_modes(options, config, scraper, fetcher).await;
+        let teardown = [
+            fetcher.stats().finish(
+                config.log.verbosity < 0,
+                &output::stats_path(config, &scraper.run_timestamp()),
+            ),
+            scraper.close_output().await,
+            fetcher.save_cookies(),
+        ];
+        for step in teardown {
+            if let Err(e) = step {
+                if result.is_ok() {
+                    result = Err(e.into());
+                } else {
+                    error!(error = %e, "Cleaning up after the failed run also failed");
+                }
+            }
+        }
+        result
+    }
+
+    async fn run_modes(
+        options: &CliOptions,
+        config: &ScraperConfig,
+        scraper: &Arc<Scraper>,
+        fetcher: &Arc<Fetcher>,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        let mut broken_links = 0;
+
+        if options.check_links {
+            let mut crawler = Crawler::new(
+                config.clone(),
+                scraper.clone(),
+                fetcher.clone(),
+                config.max_connections,
+            )?;
+            let report = crawler.check_links().await?;
+            let file_path = report.save(&config.report_format, &scraper.run_timestamp())?;
+            info!(
+                checked = report.checked,
+                broken = report.broken,
+                redirected = report.redirected,
+                report = %file_path,
+                "Link check finished"
+            );
+            broken_links = report.broken;
+        }
+
         if options.crawl {
             // Create a Crawler instance using regex patterns
-            let mut crawler =
-                Crawler::new(config.clone(), config.max_connections, options.use_proxies);
-            if options.use_regex.is_some() {
+            let mut crawler = Crawler::new(
+                config.clone(),
+                scraper.clone(),
+                fetcher.clone(),
+                config.max_connections,
+            )?;
+            if !options.use_regex.is_empty() {
                 let selectors: Vec<String> = options
                     .use_regex
-                    .as_ref()
-                    .map(|s| vec![s.trim().to_owned()])
-                    .unwrap_or_else(Vec::new);
+                    .iter()
+                    .map(|s| s.trim().to_owned())
+                    .collect();
                 crawler.run(&selectors).await?;
             } else {
                 // Create a Crawler instance using CSS selectors
@@ -44,62 +152,77 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     .use_selectors
                     .as_ref()
                     .map(|s| s.split(',').map(|part| part.trim().to_owned()).collect())
-                    .unwrap_or_else(Vec::new);
+                    .unwrap_or_default();
                 crawler.run(&selectors).await?;
             }
         }
 
         // List ALL CSS selectors from a page
         if options.list_selectors {
-            let url = &config.base_url;
-            println!("Fetching page: {}", url);
-            let response = reqwest::get(url).await?;
-            println!("Page fetched successfully.");
-            let html = response.text().await?;
-            println!("Extracting CSS Selectors from the page...");
+            let url = config.start_url();
+            let page = fetcher.fetch(&url).await?;
             let selector_extractor = SelectorExtractor::new();
             let selectors =
-                selector_extractor.extract_css_selectors(&html, options.include_duplicates);
-            println!("CSS Selectors found in the page:");
+                selector_extractor.extract_css_selectors(&page.body, options.include_duplicates);
+            // The selectors are this mode's output, so they go to stdout
             for selector in selectors {
                 println!("{}", selector);
             }
         }
 
-        // Scraping data using CSS selectors
-        if options.scrape && options.use_selectors.is_some() {
-            let scraper = Scraper::new(config.clone());
+        // Fetch the start page once for every extraction mode below
+        let extracts = options.scrape
+            || config.feed
+            || !config.xpaths.is_empty()
+            || (!options.crawl && (config.metadata || config.table_selector.is_some()));
+        if !extracts {
+            return Ok(broken_links);
+        }
+        let page = scraper.fetch(&config.start_url()).await?;
+
+        // Scraping data using CSS selectors and/or JSON queries
+        if options.scrape && (options.use_selectors.is_some() || config.has_json_queries()) {
             let selectors: Vec<String> = options
                 .use_selectors
                 .as_ref()
                 .map(|s| s.split(',').map(|part| part.trim().to_owned()).collect())
-                .unwrap_or_else(Vec::new);
-            println!("Scraping data using provided CSS selectors...");
-            scraper.scrape_data(&selectors).await?;
+                .unwrap_or_default();
+            scraper.scrape_data(&page, &selectors).await?;
         }
 
         // Scraping data using regex patterns
-        if options.scrape && options.use_regex.is_some() {
-            let config = ScraperConfig::from_options(options.clone())?;
-            let scraper = Scraper::new(config.clone());
+        if options.scrape && !options.use_regex.is_empty() {
             let regex_patterns: Vec<String> = options
                 .use_regex
-                .as_ref()
-                .map(|s| vec![s.trim().to_owned()])
-                .unwrap_or_else(Vec::new);
-            println!("Scraping data using provided regex patterns...");
-            println!("Regex patterns: {:?}", regex_patterns);
-            scraper.scrape_data_with_regex(&regex_patterns).await?;
+                .iter()
+                .map(|s| s.trim().to_owned())
+                .collect();
+            scraper
+                .scrape_data_with_regex(&page, &regex_patterns)
+                .await?;
         }
 
-        Ok(())
+        // Parsing RSS/Atom feeds and XML documents
+        if config.feed || !config.xpaths.is_empty() {
+            scraper.scrape_feed(&page).await?;
+        }
+
+        // Extracting structured metadata and tables (the crawler already does this per page)
+        if config.metadata && !options.crawl {
+            scraper.scrape_metadata(&page).await?;
+        }
+        if config.table_selector.is_some() && !options.crawl {
+            scraper.scrape_tables(&page).await?;
+        }
+
+        Ok(broken_links)
     }
 
     // Run the scraper once or run it in an interval
     if let Some(interval_str) = &options.interval {
         let interval_parts: Vec<&str> = interval_str.split(':').collect();
         if interval_parts.len() != 3 {
-            eprintln!("Invalid interval format. Use HH:MM:SS.");
+            error!(interval = %interval_str, "Invalid interval format. Use HH:MM:SS.");
             std::process::exit(1);
         }
         // HH:MM:SS
@@ -108,20 +231,26 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         let seconds: u64 = interval_parts[2].parse().unwrap();
         let interval_duration = Duration::from_secs(hours * 3600 + minutes * 60 + seconds);
 
-        loop {
-            println!("Running scraper at {}", chrono::Utc::now());
-            if let Err(e) = run_scraper(&options, &config).await {
-                eprintln!("Error during scraper run: {}", e);
+        for run in 1.. {
+            let span = tracing::error_span!("run", run);
+            if let Err(e) = run_scraper(&options, &config, &scraper, &fetcher, &storage)
+                .instrument(span)
+                .await
+            {
+                error!(run, error = %e, "Scraper run failed");
             }
 
-            println!(
-                "Waiting for {} seconds before running scraper again...",
-                interval_duration.as_secs()
+            info!(
+                seconds = interval_duration.as_secs(),
+                "Waiting before running the scraper again"
             );
             sleep(interval_duration).await;
         }
     } else {
-        run_scraper(&options, &config).await?;
+        let broken_links = run_scraper(&options, &config, &scraper, &fetcher, &storage).await?;
+        if broken_links > 0 {
+            std::process::exit(2);
+        }
     }
 
     Ok(())
@@ -143,20 +272,10 @@ fn display_welcome_message() {
                                                                             |_|                 
                                                  "#;
 
-    let mut colors = vec![
-        "red",
-        "green",
-        "yellow",
-        "blue",
-        "magenta",
-        "cyan",
-        "white",
-    ];
+    let mut colors = ["red", "green", "yellow", "blue", "magenta", "cyan", "white"];
     let mut rng = thread_rng();
     colors.shuffle(&mut rng);
-    let mut index = 0;
-    for line in logo.lines() {
+    for (index, line) in logo.lines().enumerate() {
         println!("{}", line.color(colors[index % colors.len()]).bold());
-        index += 1;
     }
-}
\ No newline at end of file
+}
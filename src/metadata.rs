@@ -0,0 +1,336 @@
+use scraper::{ElementRef, Html, Selector};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tracing::warn;
+
+/// A block of structured metadata found on a page.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MetadataRecord {
+    pub url: String,
+    /// One of `json-ld`, `microdata`, `rdfa`, `opengraph`, `twitter` or `meta`.
+    pub format: String,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub item_type: Option<String>,
+    pub data: Value,
+}
+
+// Meta property prefixes that belong to the OpenGraph protocol and its object types
+const OPENGRAPH_PREFIXES: &[&str] = &[
+    "og:", "article:", "product:", "book:", "profile:", "music:", "video:", "fb:",
+];
+
+pub struct MetadataExtractor;
+
+impl MetadataExtractor {
+    pub fn new() -> Self {
+        MetadataExtractor
+    }
+
+    pub fn extract(&self, url: &str, html: &str) -> Vec<MetadataRecord> {
+        let document = Html::parse_document(html);
+        let mut records = Vec::new();
+
+        records.extend(self.extract_json_ld(url, &document));
+        records.extend(self.extract_microdata(url, &document));
+        records.extend(self.extract_rdfa(url, &document));
+        records.extend(self.extract_meta_tags(url, &document));
+
+        records
+    }
+
+    fn extract_json_ld(&self, url: &str, document: &Html) -> Vec<MetadataRecord> {
+        let selector = Selector::parse("script[type='application/ld+json']").unwrap();
+        let mut records = Vec::new();
+
+        for script in document.select(&selector) {
+            let text = script.text().collect::<String>();
+            let value: Value = match serde_json::from_str(text.trim()) {
+                Ok(value) => value,
+                Err(err) => {
+                    warn!(url = %url, error = %err, "Invalid JSON-LD block");
+                    continue;
+                }
+            };
+
+            // A block may hold a single item, an array of items or an @graph
+            let items = match value {
+                Value::Array(items) => items,
+                Value::Object(mut object) if object.contains_key("@graph") => {
+                    match object.remove("@graph") {
+                        Some(Value::Array(items)) => items,
+                        Some(item) => vec![item],
+                        None => Vec::new(),
+                    }
+                }
+                item => vec![item],
+            };
+
+            for item in items {
+                records.push(MetadataRecord {
+                    url: url.to_string(),
+                    format: "json-ld".to_string(),
+                    item_type: json_ld_type(&item),
+                    data: item,
+                });
+            }
+        }
+
+        records
+    }
+
+    fn extract_microdata(&self, url: &str, document: &Html) -> Vec<MetadataRecord> {
+        // Top-level items are scopes that aren't themselves a property of another item
+        let selector = Selector::parse("[itemscope]:not([itemprop])").unwrap();
+
+        document
+            .select(&selector)
+            .map(|scope| MetadataRecord {
+                url: url.to_string(),
+                format: "microdata".to_string(),
+                item_type: scope.value().attr("itemtype").map(str::to_string),
+                data: microdata_item(scope),
+            })
+            .collect()
+    }
+
+    fn extract_rdfa(&self, url: &str, document: &Html) -> Vec<MetadataRecord> {
+        let selector = Selector::parse("[typeof]:not([property])").unwrap();
+
+        document
+            .select(&selector)
+            .map(|scope| {
+                let vocab = std::iter::once(scope)
+                    .chain(scope.ancestors().filter_map(ElementRef::wrap))
+                    .find_map(|element| element.value().attr("vocab"));
+                let item_type = scope.value().attr("typeof").map(|item_type| match vocab {
+                    Some(vocab) if !item_type.contains(':') => format!("{}{}", vocab, item_type),
+                    _ => item_type.to_string(),
+                });
+
+                MetadataRecord {
+                    url: url.to_string(),
+                    format: "rdfa".to_string(),
+                    item_type,
+                    data: rdfa_item(scope),
+                }
+            })
+            .collect()
+    }
+
+    fn extract_meta_tags(&self, url: &str, document: &Html) -> Vec<MetadataRecord> {
+        let meta_selector = Selector::parse("meta[content]").unwrap();
+        let mut opengraph = Map::new();
+        let mut twitter = Map::new();
+        let mut standard = Map::new();
+
+        let title_selector = Selector::parse("title").unwrap();
+        if let Some(title) = document.select(&title_selector).next() {
+            let title = title.text().collect::<String>();
+            insert_value(
+                &mut standard,
+                "title",
+                Value::String(title.trim().to_string()),
+            );
+        }
+
+        let canonical_selector = Selector::parse("link[rel='canonical'][href]").unwrap();
+        if let Some(href) = document
+            .select(&canonical_selector)
+            .next()
+            .and_then(|link| link.value().attr("href"))
+        {
+            insert_value(&mut standard, "canonical", Value::String(href.to_string()));
+        }
+
+        for meta in document.select(&meta_selector) {
+            // Inside an RDFa scope `property` belongs to that item, not to the page
+            if is_in_rdfa_scope(meta) {
+                continue;
+            }
+            let content = Value::String(meta.value().attr("content").unwrap_or("").to_string());
+            let key = meta
+                .value()
+                .attr("property")
+                .or_else(|| meta.value().attr("name"))
+                .map(|key| key.trim().to_ascii_lowercase());
+            let key = match key {
+                Some(key) if !key.is_empty() => key,
+                _ => continue,
+            };
+
+            if OPENGRAPH_PREFIXES
+                .iter()
+                .any(|prefix| key.starts_with(prefix))
+            {
+                insert_value(&mut opengraph, &key, content);
+            } else if key.starts_with("twitter:") {
+                insert_value(&mut twitter, &key, content);
+            } else if meta.value().attr("name").is_some() {
+                insert_value(&mut standard, &key, content);
+            }
+        }
+
+        let mut records = Vec::new();
+        for (format, data) in [
+            ("opengraph", opengraph),
+            ("twitter", twitter),
+            ("meta", standard),
+        ] {
+            if data.is_empty() {
+                continue;
+            }
+            let item_type = match format {
+                "opengraph" => data
+                    .get("og:type")
+                    .and_then(Value::as_str)
+                    .map(str::to_string),
+                "twitter" => data
+                    .get("twitter:card")
+                    .and_then(Value::as_str)
+                    .map(str::to_string),
+                _ => None,
+            };
+            records.push(MetadataRecord {
+                url: url.to_string(),
+                format: format.to_string(),
+                item_type,
+                data: Value::Object(data),
+            });
+        }
+
+        records
+    }
+}
+
+fn json_ld_type(item: &Value) -> Option<String> {
+    match item.get("@type")? {
+        Value::String(item_type) => Some(item_type.clone()),
+        Value::Array(types) => Some(
+            types
+                .iter()
+                .filter_map(Value::as_str)
+                .collect::<Vec<_>>()
+                .join(" "),
+        ),
+        _ => None,
+    }
+}
+
+/// Adds a value under `key`, turning repeated keys into arrays.
+fn insert_value(object: &mut Map<String, Value>, key: &str, value: Value) {
+    match object.get_mut(key) {
+        None => {
+            object.insert(key.to_string(), value);
+        }
+        Some(Value::Array(values)) => values.push(value),
+        Some(existing) => {
+            let first = existing.take();
+            *existing = Value::Array(vec![first, value]);
+        }
+    }
+}
+
+/// Returns the elements carrying `prop_attr` whose nearest enclosing scope
+/// (an element with `scope_attr`) is `scope` itself.
+fn scoped_properties<'a>(
+    scope: ElementRef<'a>,
+    scope_attr: &'a str,
+    prop_attr: &'a str,
+) -> impl Iterator<Item = ElementRef<'a>> + 'a {
+    scope
+        .descendent_elements()
+        .skip(1)
+        .filter(move |element| element.value().attr(prop_attr).is_some())
+        .filter(move |element| {
+            element
+                .ancestors()
+                .filter_map(ElementRef::wrap)
+                .find(|ancestor| ancestor.value().attr(scope_attr).is_some())
+                .is_some_and(|owner| owner.id() == scope.id())
+        })
+}
+
+fn element_text(element: ElementRef) -> Value {
+    let text = element.text().collect::<Vec<_>>().join(" ");
+    Value::String(text.split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
+fn microdata_item(scope: ElementRef) -> Value {
+    let mut properties = Map::new();
+
+    if let Some(item_type) = scope.value().attr("itemtype") {
+        properties.insert("@type".to_string(), Value::String(item_type.to_string()));
+    }
+    if let Some(item_id) = scope.value().attr("itemid") {
+        properties.insert("@id".to_string(), Value::String(item_id.to_string()));
+    }
+
+    for element in scoped_properties(scope, "itemscope", "itemprop") {
+        let value = if element.value().attr("itemscope").is_some() {
+            microdata_item(element)
+        } else {
+            microdata_value(element)
+        };
+        for name in element.value().attr("itemprop").unwrap().split_whitespace() {
+            insert_value(&mut properties, name, value.clone());
+        }
+    }
+
+    Value::Object(properties)
+}
+
+fn microdata_value(element: ElementRef) -> Value {
+    let value = element.value();
+    let attr = match value.name() {
+        "meta" => "content",
+        "a" | "area" | "link" => "href",
+        "audio" | "embed" | "iframe" | "img" | "source" | "track" | "video" => "src",
+        "object" => "data",
+        "time" => "datetime",
+        "data" | "meter" => "value",
+        _ => return element_text(element),
+    };
+
+    match value.attr(attr) {
+        Some(attr_value) => Value::String(attr_value.to_string()),
+        None => element_text(element),
+    }
+}
+
+fn rdfa_item(scope: ElementRef) -> Value {
+    let mut properties = Map::new();
+
+    if let Some(item_type) = scope.value().attr("typeof") {
+        properties.insert("@type".to_string(), Value::String(item_type.to_string()));
+    }
+    if let Some(resource) = scope.value().attr("resource") {
+        properties.insert("@id".to_string(), Value::String(resource.to_string()));
+    }
+
+    for element in scoped_properties(scope, "typeof", "property") {
+        let value = if element.value().attr("typeof").is_some() {
+            rdfa_item(element)
+        } else {
+            let attrs = element.value();
+            attrs
+                .attr("content")
+                .or_else(|| attrs.attr("resource"))
+                .or_else(|| attrs.attr("href"))
+                .or_else(|| attrs.attr("src"))
+                .map(|attr_value| Value::String(attr_value.to_string()))
+                .unwrap_or_else(|| element_text(element))
+        };
+        for name in element.value().attr("property").unwrap().split_whitespace() {
+            insert_value(&mut properties, name, value.clone());
+        }
+    }
+
+    Value::Object(properties)
+}
+
+fn is_in_rdfa_scope(element: ElementRef) -> bool {
+    element
+        .ancestors()
+        .filter_map(ElementRef::wrap)
+        .any(|ancestor| ancestor.value().attr("typeof").is_some())
+}
@@ -0,0 +1,919 @@
+use async_trait::async_trait;
+use chrono::prelude::*;
+use rusqlite::{types::ToSqlOutput, Connection, ToSql};
+use serde_json::{Map, Value};
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+    time::Duration,
+};
+use tokio::{sync::mpsc, task::JoinHandle};
+use tracing::warn;
+
+use crate::{config::ScraperConfig, fetch::Fetcher};
+
+/// Destination for the records extracted during a run. One sink is opened per
+/// run and every extractor writes through it.
+#[async_trait]
+pub trait OutputSink: Send {
+    async fn write(&mut self, record: &Value) -> io::Result<()>;
+
+    /// Flushes anything buffered and closes the output.
+    async fn finish(&mut self) -> io::Result<()>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Ndjson,
+    Json,
+    Csv,
+    Sqlite,
+}
+
+impl OutputFormat {
+    pub fn parse(name: &str) -> Result<OutputFormat, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "ndjson" | "jsonl" => Ok(OutputFormat::Ndjson),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            "sqlite" | "db" => Ok(OutputFormat::Sqlite),
+            other => Err(format!("Unknown output format '{}'", other)),
+        }
+    }
+
+    /// Guesses the format from an output path's extension.
+    pub fn from_path(path: &str) -> OutputFormat {
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("json") => OutputFormat::Json,
+            Some("csv") => OutputFormat::Csv,
+            Some("db") | Some("sqlite") | Some("sqlite3") => OutputFormat::Sqlite,
+            _ => OutputFormat::Ndjson,
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            // Kept as output.json, which is what earlier versions wrote line by line
+            OutputFormat::Ndjson | OutputFormat::Json => "json",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Sqlite => "sqlite",
+        }
+    }
+}
+
+/// Opens the sink configured for this run. Without an explicit output path the
+/// records go to `Results/<timestamp>_output.<ext>`, one file per run; an
+/// explicit path is appended to by every run; `-` streams NDJSON to stdout.
+pub fn open_sink(
+    config: &ScraperConfig,
+    fetcher: &Fetcher,
+    timestamp: &str,
+) -> io::Result<Box<dyn OutputSink>> {
+    if config.streams_to_stdout() {
+        return Ok(Box::new(NdjsonSink::new(Box::new(io::stdout()))));
+    }
+    if let Some(settings) = &config.webhook {
+        let builder = fetcher
+            .webhook_client_builder(&settings.url)
+            .map_err(|err| io::Error::other(err.to_string()))?;
+        return Ok(Box::new(WebhookSink::new(settings.clone(), builder)?));
+    }
+
+    let format = config.output_format;
+    let path = match &config.output_path {
+        Some(path) => path.clone(),
+        None => {
+            fs::create_dir_all("Results")?;
+            format!("Results/{}_output.{}", timestamp, format.extension())
+        }
+    };
+
+    if let Some(parent) = Path::new(&path).parent() {
+        if !parent.as_os_str().is_empty() {
+            fs::create_dir_all(parent)?;
+        }
+    }
+
+    Ok(match format {
+        OutputFormat::Ndjson => Box::new(NdjsonSink::new(open_append(&path)?)),
+        OutputFormat::Json => Box::new(JsonArraySink::open(&path)?),
+        OutputFormat::Csv => Box::new(CsvSink::open(&path)?),
+        OutputFormat::Sqlite => Box::new(SqliteSink::open(&path, &config.output_table)?),
+    })
+}
+
+/// Where a run's statistics go: `Results/<timestamp>_stats.json` beside the
+/// run's own output file, or `<name>_<timestamp>_stats.json` next to an
+/// explicit output file that every run appends to.
+pub fn stats_path(config: &ScraperConfig, timestamp: &str) -> String {
+    match &config.output_path {
+        Some(path) if !config.streams_to_stdout() && config.webhook.is_none() => {
+            let path = Path::new(path);
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            path.with_file_name(format!("{}_{}_stats.json", stem, timestamp))
+                .to_string_lossy()
+                .to_string()
+        }
+        _ => format!("Results/{}_stats.json", timestamp),
+    }
+}
+
+fn open_append(path: &str) -> io::Result<Box<dyn Write + Send>> {
+    Ok(Box::new(append_file(path)?))
+}
+
+fn append_file(path: &str) -> io::Result<File> {
+    fs::OpenOptions::new().append(true).create(true).open(path)
+}
+
+/// One JSON object per line.
+pub struct NdjsonSink {
+    writer: BufWriter<Box<dyn Write + Send>>,
+}
+
+impl NdjsonSink {
+    pub fn new(writer: Box<dyn Write + Send>) -> Self {
+        NdjsonSink {
+            writer: BufWriter::new(writer),
+        }
+    }
+}
+
+#[async_trait]
+impl OutputSink for NdjsonSink {
+    async fn write(&mut self, record: &Value) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, record)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()
+    }
+
+    async fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// A single JSON array of records. An array left in the file by an earlier
+/// run is reopened and extended rather than replaced.
+pub struct JsonArraySink {
+    writer: BufWriter<File>,
+    // The opening bracket is in the file
+    started: bool,
+    has_records: bool,
+    finished: bool,
+}
+
+impl JsonArraySink {
+    pub fn open(path: &str) -> io::Result<Self> {
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let not_an_array = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} does not hold a JSON array, can't append to it", path),
+            )
+        };
+
+        let len = file.metadata()?.len();
+        let (started, has_records) = match last_non_whitespace(&mut file, len)? {
+            None => (false, false),
+            Some((end, b']')) => {
+                if first_non_whitespace(&mut file)? != Some(b'[') {
+                    return Err(not_an_array());
+                }
+                // Drop the closing bracket; finish writes it back
+                let (last, byte) = last_non_whitespace(&mut file, end)?.ok_or_else(not_an_array)?;
+                file.set_len(last + 1)?;
+                (true, byte != b'[')
+            }
+            Some(_) => return Err(not_an_array()),
+        };
+        file.seek(SeekFrom::End(0))?;
+
+        Ok(JsonArraySink {
+            writer: BufWriter::new(file),
+            started,
+            has_records,
+            finished: false,
+        })
+    }
+}
+
+/// The first byte of `file` that isn't whitespace.
+fn first_non_whitespace(file: &mut File) -> io::Result<Option<u8>> {
+    file.seek(SeekFrom::Start(0))?;
+    for byte in io::BufReader::new(&mut *file).bytes() {
+        let byte = byte?;
+        if !byte.is_ascii_whitespace() {
+            return Ok(Some(byte));
+        }
+    }
+    Ok(None)
+}
+
+/// The offset and value of the last byte before `end` that isn't whitespace,
+/// read backwards so large files aren't loaded whole.
+fn last_non_whitespace(file: &mut File, end: u64) -> io::Result<Option<(u64, u8)>> {
+    let mut buffer = [0u8; 4096];
+    let mut end = end;
+    while end > 0 {
+        let start = end.saturating_sub(buffer.len() as u64);
+        let chunk = &mut buffer[..(end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(chunk)?;
+        if let Some(index) = chunk.iter().rposition(|byte| !byte.is_ascii_whitespace()) {
+            return Ok(Some((start + index as u64, chunk[index])));
+        }
+        end = start;
+    }
+    Ok(None)
+}
+
+#[async_trait]
+impl OutputSink for JsonArraySink {
+    async fn write(&mut self, record: &Value) -> io::Result<()> {
+        let separator: &[u8] = match (self.started, self.has_records) {
+            (false, _) => b"[\n  ",
+            (true, false) => b"\n  ",
+            (true, true) => b",\n  ",
+        };
+        self.writer.write_all(separator)?;
+        serde_json::to_writer(&mut self.writer, record)?;
+        self.writer.flush()?;
+        self.started = true;
+        self.has_records = true;
+        Ok(())
+    }
+
+    async fn finish(&mut self) -> io::Result<()> {
+        if !self.finished {
+            let end: &[u8] = match (self.started, self.has_records) {
+                (false, _) => b"[]\n",
+                (true, false) => b"]\n",
+                (true, true) => b"\n]\n",
+            };
+            self.writer.write_all(end)?;
+            self.finished = true;
+        }
+        self.writer.flush()
+    }
+}
+
+/// CSV with one column per field, streamed row by row. The header starts from
+/// the file an earlier run left, or else the first record. A field that isn't
+/// in it yet widens the header, rewriting the rows already written with the
+/// new column left empty.
+pub struct CsvSink {
+    path: String,
+    writer: csv::Writer<File>,
+    columns: Vec<String>,
+}
+
+impl CsvSink {
+    pub fn open(path: &str) -> io::Result<Self> {
+        let columns = match fs::metadata(path) {
+            Ok(metadata) if metadata.len() > 0 => {
+                let mut reader = csv::Reader::from_path(path)?;
+                reader.headers()?.iter().map(str::to_string).collect()
+            }
+            _ => Vec::new(),
+        };
+
+        Ok(CsvSink {
+            path: path.to_string(),
+            writer: csv::Writer::from_writer(append_file(path)?),
+            columns,
+        })
+    }
+
+    /// Rewrites the file under the current header, padding the existing rows.
+    fn rewrite(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        let mut reader = csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_path(&self.path)?;
+        let temp_path = format!("{}.tmp", self.path);
+        let mut writer = csv::Writer::from_path(&temp_path)?;
+        writer.write_record(&self.columns)?;
+        for row in reader.records() {
+            let row = row?;
+            writer.write_record(
+                (0..self.columns.len()).map(|column| row.get(column).unwrap_or_default()),
+            )?;
+        }
+        writer.flush()?;
+        drop(writer);
+
+        fs::rename(&temp_path, &self.path)?;
+        self.writer = csv::Writer::from_writer(append_file(&self.path)?);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl OutputSink for CsvSink {
+    async fn write(&mut self, record: &Value) -> io::Result<()> {
+        let row = flatten_record(record);
+        let new_columns: Vec<String> = row
+            .keys()
+            .filter(|key| !self.columns.contains(key))
+            .cloned()
+            .collect();
+
+        if !new_columns.is_empty() {
+            let had_header = !self.columns.is_empty();
+            self.columns.extend(new_columns);
+            if had_header {
+                self.rewrite()?;
+            } else {
+                self.writer.write_record(&self.columns)?;
+            }
+        }
+
+        self.writer.write_record(
+            self.columns
+                .iter()
+                .map(|column| row.get(column).map(cell_text).unwrap_or_default()),
+        )?;
+        self.writer.flush()
+    }
+
+    async fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// Columns the SQLite sink adds to every row; record fields by these names get a
+// trailing underscore instead of clashing with them
+const SQLITE_ID_COLUMN: &str = "_id";
+const SQLITE_SCRAPED_AT_COLUMN: &str = "_scraped_at";
+
+/// Rows in a SQLite table whose columns grow as new fields appear.
+pub struct SqliteSink {
+    connection: Connection,
+    table: String,
+    columns: Vec<String>,
+}
+
+impl SqliteSink {
+    pub fn open(path: &str, table: &str) -> io::Result<Self> {
+        let connection = Connection::open(path).map_err(io::Error::other)?;
+        let table = quote_identifier(table);
+        connection
+            .execute_batch(&format!(
+                "CREATE TABLE IF NOT EXISTS {} ({} INTEGER PRIMARY KEY AUTOINCREMENT, {} TEXT NOT NULL)",
+                table,
+                quote_identifier(SQLITE_ID_COLUMN),
+                quote_identifier(SQLITE_SCRAPED_AT_COLUMN)
+            ))
+            .map_err(io::Error::other)?;
+
+        // Pick up the columns of a table left by an earlier run
+        let mut columns = Vec::new();
+        {
+            let mut statement = connection
+                .prepare(&format!("PRAGMA table_info({})", table))
+                .map_err(io::Error::other)?;
+            let names = statement
+                .query_map([], |row| row.get::<_, String>(1))
+                .map_err(io::Error::other)?;
+            for name in names {
+                columns.push(name.map_err(io::Error::other)?);
+            }
+        }
+
+        Ok(SqliteSink {
+            connection,
+            table,
+            columns,
+        })
+    }
+}
+
+#[async_trait]
+impl OutputSink for SqliteSink {
+    async fn write(&mut self, record: &Value) -> io::Result<()> {
+        let mut row = Map::new();
+        for (key, value) in flatten_record(record) {
+            let column = sqlite_column(key, &self.columns, &row);
+            row.insert(column, value);
+        }
+
+        for key in row.keys() {
+            if !self.columns.contains(key) {
+                self.connection
+                    .execute_batch(&format!(
+                        "ALTER TABLE {} ADD COLUMN {}",
+                        self.table,
+                        quote_identifier(key)
+                    ))
+                    .map_err(io::Error::other)?;
+                self.columns.push(key.clone());
+            }
+        }
+
+        let names: Vec<String> = std::iter::once(quote_identifier(SQLITE_SCRAPED_AT_COLUMN))
+            .chain(row.keys().map(|key| quote_identifier(key)))
+            .collect();
+        let placeholders = vec!["?"; names.len()].join(", ");
+        let scraped_at = Value::String(Local::now().to_rfc3339());
+        let values: Vec<SqlValue> = std::iter::once(&scraped_at)
+            .chain(row.values())
+            .map(SqlValue)
+            .collect();
+
+        self.connection
+            .execute(
+                &format!(
+                    "INSERT INTO {} ({}) VALUES ({})",
+                    self.table,
+                    names.join(", "),
+                    placeholders
+                ),
+                rusqlite::params_from_iter(values.iter()),
+            )
+            .map_err(io::Error::other)?;
+        Ok(())
+    }
+
+    async fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Longest wait between two attempts at sending a webhook batch
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug)]
+pub struct WebhookSettings {
+    pub url: String,
+    pub batch_size: usize,
+    pub flush_interval: Duration,
+    pub retries: u32,
+    pub headers: Vec<(String, String)>,
+    pub dead_letter_path: String,
+}
+
+/// POSTs records to an HTTP endpoint as JSON arrays. Records are handed to a
+/// background task that sends a batch once it is full or the flush interval
+/// passes, whichever comes first.
+pub struct WebhookSink {
+    sender: Option<mpsc::UnboundedSender<Value>>,
+    task: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl WebhookSink {
+    /// Starts the sender task on a client from `builder`, which carries the
+    /// proxy and TLS setup.
+    pub fn new(settings: WebhookSettings, builder: reqwest::ClientBuilder) -> io::Result<Self> {
+        let mut headers = reqwest::header::HeaderMap::new();
+        for (name, value) in &settings.headers {
+            let name = reqwest::header::HeaderName::from_bytes(name.as_bytes())
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+            let value = reqwest::header::HeaderValue::from_str(value)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+            headers.append(name, value);
+        }
+        let client = builder
+            .timeout(Duration::from_secs(30))
+            .build()
+            .map_err(io::Error::other)?;
+
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let task = tokio::spawn(run_webhook(client, settings, headers, receiver));
+
+        Ok(WebhookSink {
+            sender: Some(sender),
+            task: Some(task),
+        })
+    }
+}
+
+#[async_trait]
+impl OutputSink for WebhookSink {
+    async fn write(&mut self, record: &Value) -> io::Result<()> {
+        match &self.sender {
+            Some(sender) => sender
+                .send(record.clone())
+                .map_err(|_| io::Error::other("webhook sender has stopped")),
+            None => Err(io::Error::other("webhook sink is already finished")),
+        }
+    }
+
+    async fn finish(&mut self) -> io::Result<()> {
+        // Closing the channel makes the task send what is left and exit
+        self.sender.take();
+        match self.task.take() {
+            Some(task) => task.await.map_err(io::Error::other)?,
+            None => Ok(()),
+        }
+    }
+}
+
+async fn run_webhook(
+    client: reqwest::Client,
+    settings: WebhookSettings,
+    // Set on each request rather than as client defaults, which would keep
+    // only one value of a repeated header
+    headers: reqwest::header::HeaderMap,
+    mut receiver: mpsc::UnboundedReceiver<Value>,
+) -> io::Result<()> {
+    let mut batch = Vec::with_capacity(settings.batch_size);
+    let mut ticker = tokio::time::interval(settings.flush_interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    // The first tick completes immediately
+    ticker.tick().await;
+
+    loop {
+        tokio::select! {
+            record = receiver.recv() => match record {
+                Some(record) => {
+                    batch.push(record);
+                    if batch.len() >= settings.batch_size {
+                        send_batch(&client, &settings, &headers, std::mem::take(&mut batch)).await?;
+                        ticker.reset();
+                    }
+                }
+                None => break,
+            },
+            _ = ticker.tick() => {
+                if !batch.is_empty() {
+                    send_batch(&client, &settings, &headers, std::mem::take(&mut batch)).await?;
+                }
+            }
+        }
+    }
+
+    if !batch.is_empty() {
+        send_batch(&client, &settings, &headers, batch).await?;
+    }
+    Ok(())
+}
+
+/// Sends one batch, retrying with exponential backoff. A batch that still
+/// fails is appended to the dead-letter file instead of being lost.
+async fn send_batch(
+    client: &reqwest::Client,
+    settings: &WebhookSettings,
+    headers: &reqwest::header::HeaderMap,
+    batch: Vec<Value>,
+) -> io::Result<()> {
+    let mut delay = Duration::from_millis(500);
+    let mut last_error = String::new();
+
+    for attempt in 0..=settings.retries {
+        if attempt > 0 {
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(MAX_BACKOFF);
+        }
+
+        match client
+            .post(&settings.url)
+            .headers(headers.clone())
+            .json(&batch)
+            .send()
+            .await
+        {
+            Ok(response) if response.status().is_success() => return Ok(()),
+            Ok(response) => last_error = format!("HTTP {}", response.status()),
+            Err(err) => last_error = err.to_string(),
+        }
+        warn!(
+            records = batch.len(),
+            attempt = attempt + 1,
+            attempts = settings.retries + 1,
+            error = %last_error,
+            "Webhook batch failed"
+        );
+    }
+
+    warn!(
+        records = batch.len(),
+        file = %settings.dead_letter_path,
+        "Writing batch to the dead-letter file"
+    );
+    if let Some(parent) = Path::new(&settings.dead_letter_path).parent() {
+        if !parent.as_os_str().is_empty() {
+            fs::create_dir_all(parent)?;
+        }
+    }
+    let mut file = open_append(&settings.dead_letter_path)?;
+    let entry = serde_json::json!({
+        "failed_at": Local::now().to_rfc3339(),
+        "url": settings.url,
+        "error": last_error,
+        "records": batch,
+    });
+    writeln!(file, "{}", entry)
+}
+
+struct SqlValue<'a>(&'a Value);
+
+impl ToSql for SqlValue<'_> {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(match self.0 {
+            Value::Null => ToSqlOutput::from(rusqlite::types::Null),
+            Value::Bool(value) => ToSqlOutput::from(*value),
+            Value::Number(number) => match number.as_i64() {
+                Some(integer) => ToSqlOutput::from(integer),
+                None => ToSqlOutput::from(number.as_f64().unwrap_or_default()),
+            },
+            Value::String(text) => ToSqlOutput::from(text.as_str()),
+            other => ToSqlOutput::from(other.to_string()),
+        })
+    }
+}
+
+/// Top-level fields of a record; anything that isn't an object is stored
+/// under `content`.
+fn flatten_record(record: &Value) -> Map<String, Value> {
+    match record {
+        Value::Object(fields) => fields.clone(),
+        other => {
+            let mut fields = Map::new();
+            fields.insert("content".to_string(), other.clone());
+            fields
+        }
+    }
+}
+
+fn cell_text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// The column a record field is stored in. SQLite matches column names without
+/// regard to ASCII case, so the field takes the spelling of an existing column
+/// it matches, and gets a trailing underscore when it would clash with the
+/// sink's own columns or with another field of the same row.
+fn sqlite_column(key: String, columns: &[String], row: &Map<String, Value>) -> String {
+    let mut column = key;
+    while [SQLITE_ID_COLUMN, SQLITE_SCRAPED_AT_COLUMN]
+        .iter()
+        .copied()
+        .chain(row.keys().map(String::as_str))
+        .any(|taken| taken.eq_ignore_ascii_case(&column))
+    {
+        column.push('_');
+    }
+    columns
+        .iter()
+        .find(|existing| existing.eq_ignore_ascii_case(&column))
+        .cloned()
+        .unwrap_or(column)
+}
+
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feed::FeedEntry;
+    use crate::test_support::{temp_path, TestServer};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn json_array_runs_extend_the_same_array() {
+        let path = temp_path("array.json");
+        let path = path.to_str().unwrap();
+
+        for records in [
+            vec![json!({"a": 1})],
+            vec![],
+            vec![json!({"a": 2}), json!({"a": 3})],
+        ] {
+            let mut sink = JsonArraySink::open(path).unwrap();
+            for record in &records {
+                sink.write(record).await.unwrap();
+            }
+            sink.finish().await.unwrap();
+        }
+
+        let written: Value = serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(written, json!([{"a": 1}, {"a": 2}, {"a": 3}]));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn json_array_starts_from_an_empty_array() {
+        let path = temp_path("empty.json");
+        let path = path.to_str().unwrap();
+        fs::write(path, "[]\n\n").unwrap();
+
+        let mut sink = JsonArraySink::open(path).unwrap();
+        sink.write(&json!("x")).await.unwrap();
+        sink.finish().await.unwrap();
+
+        let written: Value = serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(written, json!(["x"]));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn json_array_refuses_other_files() {
+        let path = temp_path("object.json");
+        let path = path.to_str().unwrap();
+        fs::write(path, "{\"a\": [1]}").unwrap();
+
+        let err = JsonArraySink::open(path).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(fs::read_to_string(path).unwrap(), "{\"a\": [1]}");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn csv_widens_the_header_for_new_fields() {
+        let path = temp_path("rows.csv");
+        let path = path.to_str().unwrap();
+
+        let mut sink = CsvSink::open(path).unwrap();
+        sink.write(&json!({"title": "One", "price": 1}))
+            .await
+            .unwrap();
+        // Rows are on disk before the run finishes
+        assert_eq!(fs::read_to_string(path).unwrap(), "title,price\nOne,1\n");
+        sink.write(&json!({"price": 2, "extra": "kept"}))
+            .await
+            .unwrap();
+        sink.finish().await.unwrap();
+        assert_eq!(
+            fs::read_to_string(path).unwrap(),
+            "title,price,extra\nOne,1,\n,2,kept\n"
+        );
+
+        // The next run keeps the existing header
+        let mut sink = CsvSink::open(path).unwrap();
+        sink.write(&json!({"price": 3, "title": "Three"}))
+            .await
+            .unwrap();
+        sink.finish().await.unwrap();
+
+        assert_eq!(
+            fs::read_to_string(path).unwrap(),
+            "title,price,extra\nOne,1,\n,2,kept\nThree,3,\n"
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn sqlite_matches_columns_without_regard_to_case() {
+        let path = temp_path("case.db");
+
+        let mut sink = SqliteSink::open(path.to_str().unwrap(), "records").unwrap();
+        sink.write(&json!({"Name": "a"})).await.unwrap();
+        sink.write(&json!({"name": "b", "NAME": "c", "_ID": 7}))
+            .await
+            .unwrap();
+        sink.finish().await.unwrap();
+
+        let connection = Connection::open(&path).unwrap();
+        let rows: Vec<(String, Option<String>, Option<i64>)> = connection
+            .prepare("SELECT \"Name\", \"NAME_\", \"_ID_\" FROM records ORDER BY \"_id\"")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            [
+                ("a".to_string(), None, None),
+                ("b".to_string(), Some("c".to_string()), Some(7))
+            ]
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn sqlite_keeps_record_fields_apart_from_its_own_columns() {
+        let path = temp_path("feed.db");
+        let entry = FeedEntry {
+            id: "urn:uuid:1".to_string(),
+            title: Some("First post".to_string()),
+            link: None,
+            published: None,
+            author: None,
+            summary: None,
+        };
+
+        let mut sink = SqliteSink::open(path.to_str().unwrap(), "records").unwrap();
+        sink.write(&serde_json::to_value(&entry).unwrap())
+            .await
+            .unwrap();
+        sink.write(&serde_json::json!({"_id": 7, "_scraped_at": "yesterday", "scraped_at": "x"}))
+            .await
+            .unwrap();
+        sink.finish().await.unwrap();
+
+        let connection = Connection::open(&path).unwrap();
+        let (row_id, id, title): (i64, String, String) = connection
+            .query_row(
+                "SELECT \"_id\", \"id\", \"title\" FROM records WHERE \"_id\" = 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(
+            (row_id, id.as_str(), title.as_str()),
+            (1, "urn:uuid:1", "First post")
+        );
+        let (id_field, scraped_at_field): (i64, String) = connection
+            .query_row(
+                "SELECT \"_id_\", \"_scraped_at_\" FROM records WHERE \"_id\" = 2",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((id_field, scraped_at_field.as_str()), (7, "yesterday"));
+
+        // A second run appends to the same table
+        let mut sink = SqliteSink::open(path.to_str().unwrap(), "records").unwrap();
+        sink.write(&serde_json::to_value(&entry).unwrap())
+            .await
+            .unwrap();
+        let count: i64 = connection
+            .query_row("SELECT COUNT(*) FROM records", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 3);
+        fs::remove_file(&path).unwrap();
+    }
+
+    fn webhook_settings(url: String, retries: u32, dead_letter_path: &str) -> WebhookSettings {
+        WebhookSettings {
+            url,
+            batch_size: 2,
+            flush_interval: Duration::from_secs(60),
+            retries,
+            headers: vec![
+                ("X-Tag".to_string(), "one".to_string()),
+                ("X-Tag".to_string(), "two".to_string()),
+            ],
+            dead_letter_path: dead_letter_path.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_a_failed_batch_with_every_header() {
+        let server = TestServer::start(None, vec![503]).await;
+        let url = format!("http://{}/hook", server.addr);
+        let dead_letter = temp_path("retried.ndjson");
+        let dead_letter = dead_letter.to_str().unwrap();
+        let mut sink = WebhookSink::new(
+            webhook_settings(url, 2, dead_letter),
+            reqwest::Client::builder(),
+        )
+        .unwrap();
+
+        for n in 1..=3 {
+            sink.write(&json!({ "n": n })).await.unwrap();
+        }
+        sink.finish().await.unwrap();
+
+        let requests = server.requests();
+        // The first batch failed once, the last one holds the leftover record
+        assert_eq!(requests.len(), 3);
+        assert!(requests[0].ends_with(r#"[{"n":1},{"n":2}]"#));
+        assert!(requests[1].ends_with(r#"[{"n":1},{"n":2}]"#));
+        assert!(requests[2].ends_with(r#"[{"n":3}]"#));
+        let tags: Vec<&str> = requests[0]
+            .lines()
+            .filter(|line| line.to_ascii_lowercase().starts_with("x-tag:"))
+            .map(|line| line[6..].trim())
+            .collect();
+        assert_eq!(tags, ["one", "two"]);
+        assert!(!Path::new(dead_letter).exists());
+    }
+
+    #[tokio::test]
+    async fn dead_letters_a_batch_that_keeps_failing() {
+        let server = TestServer::start(None, vec![500, 500]).await;
+        let url = format!("http://{}/hook", server.addr);
+        let dead_letter = temp_path("dead_letter.ndjson");
+        let dead_letter = dead_letter.to_str().unwrap();
+        let mut sink = WebhookSink::new(
+            webhook_settings(url.clone(), 1, dead_letter),
+            reqwest::Client::builder(),
+        )
+        .unwrap();
+
+        sink.write(&json!({ "n": 1 })).await.unwrap();
+        sink.finish().await.unwrap();
+
+        assert_eq!(server.requests().len(), 2);
+        let entry: Value =
+            serde_json::from_str(fs::read_to_string(dead_letter).unwrap().trim()).unwrap();
+        assert_eq!(entry["url"], json!(url));
+        assert_eq!(entry["error"], json!("HTTP 500 Internal Server Error"));
+        assert_eq!(entry["records"], json!([{ "n": 1 }]));
+        fs::remove_file(dead_letter).unwrap();
+    }
+}
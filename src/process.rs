@@ -0,0 +1,605 @@
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use regex::Regex;
+use serde_json::{Map, Number, Value};
+
+/// One step of a field's processor chain.
+#[derive(Clone, Debug)]
+pub enum Processor {
+    Trim,
+    Collapse,
+    DecodeEntities,
+    Lower,
+    Upper,
+    Title,
+    Replace(Regex, String),
+    Extract(Regex, usize),
+    Split(String),
+    Join(String),
+    Number(Locale),
+    Integer(Locale),
+    Price(Locale),
+    Date(Option<String>),
+    Bool(Vec<String>, Vec<String>),
+    Default(Value),
+}
+
+/// Which characters separate thousands and decimals in a number.
+#[derive(Clone, Copy, Debug)]
+pub enum Locale {
+    // 1,234.56
+    Dot,
+    // 1.234,56
+    Comma,
+    // 1 234,56 / 1'234.56 and friends; the last separator is taken as decimal
+    Auto,
+}
+
+/// Processor chains keyed by field, parsed from `field=step|step(arg)|...` specs.
+#[derive(Clone, Debug, Default)]
+pub struct FieldPipeline {
+    chains: Vec<(String, Vec<Processor>)>,
+}
+
+const DEFAULT_TRUE: &[&str] = &["true", "yes", "y", "1", "on", "in stock", "available"];
+const DEFAULT_FALSE: &[&str] = &[
+    "false",
+    "no",
+    "n",
+    "0",
+    "off",
+    "out of stock",
+    "unavailable",
+];
+
+const DATE_FORMATS: &[&str] = &[
+    "%Y-%m-%d",
+    "%Y/%m/%d",
+    "%d.%m.%Y",
+    "%d/%m/%Y",
+    "%m/%d/%Y",
+    "%B %d, %Y",
+    "%b %d, %Y",
+    "%d %B %Y",
+    "%d %b %Y",
+];
+
+const DATETIME_FORMATS: &[&str] = &[
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y-%m-%d %H:%M",
+    "%d.%m.%Y %H:%M",
+    "%d/%m/%Y %H:%M",
+    "%m/%d/%Y %H:%M",
+];
+
+impl FieldPipeline {
+    pub fn parse(specs: &[String]) -> Result<FieldPipeline, String> {
+        let mut chains = Vec::new();
+
+        for spec in specs {
+            let (field, steps) = spec.split_once('=').ok_or_else(|| {
+                format!("Invalid processor spec '{}': expected field=steps", spec)
+            })?;
+            let field = field.trim();
+            if field.is_empty() {
+                return Err(format!(
+                    "Invalid processor spec '{}': missing field name",
+                    spec
+                ));
+            }
+
+            let processors = split_outside_quotes(steps, '|')
+                .iter()
+                .map(|step| {
+                    Processor::parse(step.trim())
+                        .map_err(|err| format!("Invalid processor for field '{}': {}", field, err))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            chains.push((field.to_string(), processors));
+        }
+
+        Ok(FieldPipeline { chains })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chains.is_empty()
+    }
+
+    /// Runs every chain over its field (dotted paths reach into nested objects)
+    /// and returns the coercion errors keyed by field.
+    pub fn apply(&self, record: &mut Map<String, Value>) -> Map<String, Value> {
+        let mut errors = Map::new();
+
+        for (field, processors) in &self.chains {
+            let slot = match field_mut(record, field) {
+                Some(slot) => slot,
+                None => continue,
+            };
+            let mut value = slot.take();
+            for processor in processors {
+                value = match processor.apply(value) {
+                    Ok(value) => value,
+                    Err(err) => {
+                        errors.insert(field.clone(), Value::String(err));
+                        Value::Null
+                    }
+                };
+            }
+            *slot = value;
+        }
+
+        errors
+    }
+}
+
+impl Processor {
+    fn parse(step: &str) -> Result<Processor, String> {
+        let (name, args) = match step.find('(') {
+            Some(open) if step.ends_with(')') => (
+                step[..open].trim(),
+                split_outside_quotes(&step[open + 1..step.len() - 1], ',')
+                    .iter()
+                    .map(|arg| unquote(arg.trim()))
+                    .collect::<Vec<_>>(),
+            ),
+            Some(_) => return Err(format!("unbalanced parentheses in '{}'", step)),
+            None => (step, Vec::new()),
+        };
+        let arg = |index: usize| args.get(index).map(String::as_str);
+        let regex = |pattern: Option<&str>| {
+            Regex::new(pattern.ok_or_else(|| format!("'{}' needs a pattern", name))?)
+                .map_err(|err| format!("invalid regex in '{}': {}", step, err))
+        };
+
+        Ok(match name {
+            "trim" => Processor::Trim,
+            "collapse" => Processor::Collapse,
+            "entities" => Processor::DecodeEntities,
+            "lower" => Processor::Lower,
+            "upper" => Processor::Upper,
+            "title" => Processor::Title,
+            "replace" => Processor::Replace(regex(arg(0))?, arg(1).unwrap_or("").to_string()),
+            "extract" => Processor::Extract(
+                regex(arg(0))?,
+                match arg(1) {
+                    Some(group) => group
+                        .parse()
+                        .map_err(|_| format!("invalid group '{}' in '{}'", group, step))?,
+                    None => 0,
+                },
+            ),
+            "split" => Processor::Split(arg(0).unwrap_or(",").to_string()),
+            "join" => Processor::Join(arg(0).unwrap_or(", ").to_string()),
+            "number" => Processor::Number(Locale::parse(arg(0))?),
+            "int" => Processor::Integer(Locale::parse(arg(0))?),
+            "price" => Processor::Price(Locale::parse(arg(0))?),
+            "date" => Processor::Date(arg(0).map(str::to_string)),
+            "bool" => {
+                if args.is_empty() {
+                    Processor::Bool(
+                        DEFAULT_TRUE.iter().map(|s| s.to_string()).collect(),
+                        DEFAULT_FALSE.iter().map(|s| s.to_string()).collect(),
+                    )
+                } else {
+                    Processor::Bool(
+                        vec![args[0].to_lowercase()],
+                        args[1..].iter().map(|s| s.to_lowercase()).collect(),
+                    )
+                }
+            }
+            "default" => Processor::Default(
+                arg(0)
+                    .map(|raw| serde_json::from_str(raw).unwrap_or_else(|_| Value::from(raw)))
+                    .unwrap_or(Value::Null),
+            ),
+            other => return Err(format!("unknown processor '{}'", other)),
+        })
+    }
+
+    fn apply(&self, value: Value) -> Result<Value, String> {
+        if let Processor::Default(default) = self {
+            return Ok(match value {
+                Value::Null => default.clone(),
+                Value::String(ref text) if text.trim().is_empty() => default.clone(),
+                Value::Array(ref items) if items.is_empty() => default.clone(),
+                value => value,
+            });
+        }
+        if let Processor::Join(separator) = self {
+            return Ok(match value {
+                Value::Array(items) => Value::String(
+                    items
+                        .iter()
+                        .map(value_text)
+                        .collect::<Vec<_>>()
+                        .join(separator),
+                ),
+                value => value,
+            });
+        }
+
+        // Everything else works element-wise on text
+        match value {
+            Value::Null => Ok(Value::Null),
+            Value::Array(items) => items
+                .into_iter()
+                .map(|item| self.apply(item))
+                .collect::<Result<Vec<_>, _>>()
+                .map(Value::Array),
+            value => self.apply_text(&value_text(&value)),
+        }
+    }
+
+    fn apply_text(&self, text: &str) -> Result<Value, String> {
+        Ok(match self {
+            Processor::Trim => Value::from(text.trim()),
+            Processor::Collapse => Value::from(collapse_whitespace(text)),
+            Processor::DecodeEntities => {
+                Value::from(html_escape::decode_html_entities(text).into_owned())
+            }
+            Processor::Lower => Value::from(text.to_lowercase()),
+            Processor::Upper => Value::from(text.to_uppercase()),
+            Processor::Title => Value::from(title_case(text)),
+            Processor::Replace(regex, replacement) => {
+                Value::from(regex.replace_all(text, replacement.as_str()).into_owned())
+            }
+            Processor::Extract(regex, group) => regex
+                .captures(text)
+                .and_then(|captures| captures.get(*group))
+                .map_or(Value::Null, |m| Value::from(m.as_str())),
+            Processor::Split(separator) => Value::Array(
+                text.split(separator.as_str())
+                    .map(|part| Value::from(part.trim()))
+                    .filter(|part| part.as_str() != Some(""))
+                    .collect(),
+            ),
+            Processor::Number(locale) => parse_number(text, *locale)?,
+            Processor::Integer(locale) => match parse_number(text, *locale)? {
+                Value::Number(number) => Value::from(number.as_f64().unwrap_or(0.0).round() as i64),
+                other => other,
+            },
+            Processor::Price(locale) => {
+                // Drop currency symbols and codes, keeping the digits, separators and sign
+                let amount: String = text
+                    .chars()
+                    .filter(|c| c.is_ascii_digit() || matches!(c, '.' | ',' | '-' | '\'' | ' '))
+                    .collect();
+                parse_number(amount.trim(), *locale)
+                    .map_err(|_| format!("cannot parse '{}' as a price", text))?
+            }
+            Processor::Date(format) => Value::from(parse_date(text, format.as_deref())?),
+            Processor::Bool(truthy, falsy) => {
+                let normalized = text.trim().to_lowercase();
+                if truthy.contains(&normalized) {
+                    Value::Bool(true)
+                } else if falsy.contains(&normalized) || falsy.is_empty() {
+                    Value::Bool(false)
+                } else {
+                    return Err(format!("cannot map '{}' to a boolean", text));
+                }
+            }
+            Processor::Join(_) | Processor::Default(_) => unreachable!(),
+        })
+    }
+}
+
+impl Locale {
+    fn parse(name: Option<&str>) -> Result<Locale, String> {
+        match name.map(str::to_lowercase).as_deref() {
+            None | Some("auto") => Ok(Locale::Auto),
+            Some("en") | Some("us") | Some("uk") | Some("dot") => Ok(Locale::Dot),
+            Some("de") | Some("fr") | Some("es") | Some("it") | Some("nl") | Some("pt")
+            | Some("ru") | Some("eu") | Some("comma") => Ok(Locale::Comma),
+            Some(other) => Err(format!("unknown number locale '{}'", other)),
+        }
+    }
+}
+
+fn parse_number(text: &str, locale: Locale) -> Result<Value, String> {
+    let cleaned: String = text
+        .trim()
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '\'' && *c != '\u{a0}')
+        .collect();
+
+    let decimal = match locale {
+        Locale::Dot => '.',
+        Locale::Comma => ',',
+        Locale::Auto => {
+            // A lone separator followed by exactly three digits is a thousands separator
+            let last = cleaned.rfind(['.', ',']);
+            match last {
+                Some(index) => {
+                    let separator = cleaned[index..].chars().next().unwrap();
+                    let digits_after = cleaned.len() - index - 1;
+                    let mixed = cleaned.contains('.') && cleaned.contains(',');
+                    if mixed || (cleaned.matches(separator).count() == 1 && digits_after != 3) {
+                        separator
+                    } else if separator == '.' {
+                        // "1.234.567" or "1,234": no decimal part
+                        ','
+                    } else {
+                        '.'
+                    }
+                }
+                None => '.',
+            }
+        }
+    };
+
+    let normalized: String = cleaned
+        .chars()
+        .filter_map(|c| match c {
+            c if c == decimal => Some('.'),
+            '.' | ',' => None,
+            c => Some(c),
+        })
+        .collect();
+
+    if let Ok(integer) = normalized.parse::<i64>() {
+        return Ok(Value::from(integer));
+    }
+    normalized
+        .parse::<f64>()
+        .ok()
+        .and_then(Number::from_f64)
+        .map(Value::Number)
+        .ok_or_else(|| format!("cannot parse '{}' as a number", text))
+}
+
+fn parse_date(text: &str, format: Option<&str>) -> Result<String, String> {
+    let text = text.trim();
+
+    if let Some(format) = format {
+        if let Ok(date) = DateTime::parse_from_str(text, format) {
+            return Ok(date.to_rfc3339());
+        }
+        if let Ok(date) = NaiveDateTime::parse_from_str(text, format) {
+            return Ok(date.format("%Y-%m-%dT%H:%M:%S").to_string());
+        }
+        return NaiveDate::parse_from_str(text, format)
+            .map(|date| date.format("%Y-%m-%d").to_string())
+            .map_err(|_| format!("cannot parse '{}' as a date with format '{}'", text, format));
+    }
+
+    if let Ok(date) = DateTime::parse_from_rfc3339(text) {
+        return Ok(date.to_rfc3339());
+    }
+    if let Ok(date) = DateTime::parse_from_rfc2822(text) {
+        return Ok(date.to_rfc3339());
+    }
+    for format in DATETIME_FORMATS {
+        if let Ok(date) = NaiveDateTime::parse_from_str(text, format) {
+            return Ok(date.format("%Y-%m-%dT%H:%M:%S").to_string());
+        }
+    }
+    for format in DATE_FORMATS {
+        if let Ok(date) = NaiveDate::parse_from_str(text, format) {
+            return Ok(date.format("%Y-%m-%d").to_string());
+        }
+    }
+
+    Err(format!("cannot parse '{}' as a date", text))
+}
+
+fn value_text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn title_case(text: &str) -> String {
+    text.split(' ')
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first
+                    .to_uppercase()
+                    .chain(chars.flat_map(char::to_lowercase))
+                    .collect(),
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn field_mut<'a>(record: &'a mut Map<String, Value>, path: &str) -> Option<&'a mut Value> {
+    let mut parts = path.split('.');
+    let mut current = record.get_mut(parts.next()?)?;
+    for part in parts {
+        current = current.as_object_mut()?.get_mut(part)?;
+    }
+    Some(current)
+}
+
+/// Splits on `separator` except inside single-quoted arguments.
+fn split_outside_quotes(text: &str, separator: char) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut depth = 0;
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if in_quotes => {
+                current.push(c);
+                if let Some(escaped) = chars.next() {
+                    current.push(escaped);
+                }
+            }
+            '\'' => {
+                in_quotes = !in_quotes;
+                current.push(c);
+            }
+            '(' if !in_quotes => {
+                depth += 1;
+                current.push(c);
+            }
+            ')' if !in_quotes => {
+                depth -= 1;
+                current.push(c);
+            }
+            c if c == separator && !in_quotes && depth == 0 => {
+                parts.push(std::mem::take(&mut current));
+            }
+            c => current.push(c),
+        }
+    }
+    parts.push(current);
+
+    parts
+}
+
+fn unquote(arg: &str) -> String {
+    if arg.len() >= 2 && arg.starts_with('\'') && arg.ends_with('\'') {
+        arg[1..arg.len() - 1].replace("\\'", "'")
+    } else {
+        arg.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Runs `steps` over a record's `field`, returning the new value and any error.
+    fn process(steps: &str, value: Value) -> (Value, Option<Value>) {
+        let pipeline = FieldPipeline::parse(&[format!("field={}", steps)]).unwrap();
+        let mut record = Map::new();
+        record.insert("field".to_string(), value);
+        let errors = pipeline.apply(&mut record);
+        (record["field"].clone(), errors.get("field").cloned())
+    }
+
+    #[test]
+    fn cleans_text() {
+        assert_eq!(process("trim|collapse", json!("  a \n  b  ")).0, "a b");
+        assert_eq!(
+            process("entities", json!("Tom &amp; Jerry")).0,
+            "Tom & Jerry"
+        );
+        assert_eq!(process("title", json!("hELLO wORLD")).0, "Hello World");
+        assert_eq!(process("upper", json!("abc")).0, "ABC");
+    }
+
+    #[test]
+    fn replaces_and_extracts_with_quoted_arguments() {
+        assert_eq!(process("replace('\\s+', '-')", json!("a  b c")).0, "a-b-c");
+        assert_eq!(
+            process(
+                "extract('SKU: (\\w+)', 1)",
+                json!("Item SKU: AB12 in stock")
+            )
+            .0,
+            "AB12"
+        );
+        assert_eq!(process("extract('x(\\d)')", json!("none")).0, Value::Null);
+        // A separator inside quotes does not split the chain
+        assert_eq!(process("replace('a|b', 'c')", json!("a|b")).0, "c|c");
+    }
+
+    #[test]
+    fn splits_and_joins_lists() {
+        assert_eq!(process("split", json!("a, b,,c")).0, json!(["a", "b", "c"]));
+        assert_eq!(
+            process("split('/')|upper|join(' + ')", json!("x/y")).0,
+            "X + Y"
+        );
+    }
+
+    #[test]
+    fn parses_numbers_by_locale() {
+        assert_eq!(process("number(en)", json!("1,234.5")).0, json!(1234.5));
+        assert_eq!(process("number(de)", json!("1.234,5")).0, json!(1234.5));
+        assert_eq!(process("number", json!("1 234,56")).0, json!(1234.56));
+        assert_eq!(process("number", json!("1,234")).0, json!(1234));
+        assert_eq!(process("number", json!("1.234.567")).0, json!(1234567));
+        assert_eq!(process("number", json!("0.5")).0, json!(0.5));
+        assert_eq!(process("int", json!("2.6")).0, json!(3));
+    }
+
+    #[test]
+    fn parses_prices_without_currency() {
+        assert_eq!(process("price", json!("€ 1.299,00")).0, json!(1299.0));
+        assert_eq!(
+            process("price(en)", json!("USD 1,299.99")).0,
+            json!(1299.99)
+        );
+        let (value, error) = process("price", json!("call us"));
+        assert_eq!(value, Value::Null);
+        assert_eq!(error.unwrap(), "cannot parse 'call us' as a price");
+    }
+
+    #[test]
+    fn normalizes_dates() {
+        assert_eq!(process("date", json!("March 5, 2024")).0, "2024-03-05");
+        assert_eq!(
+            process("date", json!("05.03.2024 14:30")).0,
+            "2024-03-05T14:30:00"
+        );
+        assert_eq!(
+            process("date", json!("Tue, 05 Mar 2024 10:00:00 +0100")).0,
+            "2024-03-05T10:00:00+01:00"
+        );
+        assert_eq!(
+            process("date('%d|%m|%Y')", json!("05|03|2024")).0,
+            "2024-03-05"
+        );
+        assert!(process("date", json!("soon")).1.is_some());
+    }
+
+    #[test]
+    fn maps_booleans() {
+        assert_eq!(process("bool", json!("In Stock")).0, true);
+        assert_eq!(process("bool", json!("no")).0, false);
+        assert_eq!(process("bool('available')", json!("sold out")).0, false);
+        assert!(process("bool", json!("maybe")).1.is_some());
+    }
+
+    #[test]
+    fn fills_defaults_after_failures() {
+        assert_eq!(process("number|default(0)", json!("n/a")).0, json!(0));
+        assert_eq!(process("default('none')", json!("  ")).0, "none");
+        assert_eq!(process("default(0)", json!("5")).0, "5");
+    }
+
+    #[test]
+    fn reaches_nested_fields_and_skips_missing_ones() {
+        let pipeline = FieldPipeline::parse(&["offer.price=number".to_string()]).unwrap();
+        let mut record = json!({"offer": {"price": "12.50"}})
+            .as_object()
+            .unwrap()
+            .clone();
+        assert!(pipeline.apply(&mut record).is_empty());
+        assert_eq!(record["offer"]["price"], json!(12.5));
+
+        let mut other = json!({"name": "x"}).as_object().unwrap().clone();
+        assert!(pipeline.apply(&mut other).is_empty());
+        assert_eq!(other, *json!({"name": "x"}).as_object().unwrap());
+    }
+
+    #[test]
+    fn rejects_bad_specs() {
+        for spec in [
+            "price",
+            "=trim",
+            "x=unknown",
+            "x=replace('(')",
+            "x=extract('a', b)",
+        ] {
+            assert!(
+                FieldPipeline::parse(&[spec.to_string()]).is_err(),
+                "{}",
+                spec
+            );
+        }
+    }
+}
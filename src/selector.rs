@@ -5,6 +5,7 @@ use scraper::{Html, Selector};
 use std::fs;
 use std::fs::OpenOptions;
 use std::io::prelude::*;
+use tracing::info;
 
 pub struct SelectorExtractor;
 
@@ -25,12 +26,9 @@ impl SelectorExtractor {
             let mut parser = cssparser::Parser::new(&mut input);
 
             while let Ok(token) = parser.next() {
-                match token {
-                    cssparser::Token::Ident(ref ident) => {
-                        let selector = ident.as_ref().to_string();
-                        selectors.push(selector);
-                    }
-                    _ => (),
+                if let cssparser::Token::Ident(ref ident) = token {
+                    let selector = ident.as_ref().to_string();
+                    selectors.push(selector);
                 }
             }
         }
@@ -40,7 +38,7 @@ impl SelectorExtractor {
         } else {
             selectors.into_iter().unique().collect()
         };
-        println!("Selectors extracted: {}", selectors.len());
+        info!(selectors = selectors.len(), "Selectors extracted");
         self.save_selectors_to_file(&selectors).unwrap();
 
         selectors
@@ -58,7 +56,6 @@ impl SelectorExtractor {
 
         // Open the file with the new file path
         let mut file = OpenOptions::new()
-            .write(true)
             .append(true)
             .create(true)
             .open(file_path)?;
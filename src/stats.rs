@@ -0,0 +1,347 @@
+use chrono::prelude::*;
+use indicatif::{HumanBytes, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tracing::info;
+
+use crate::console;
+
+// How many error causes and slow URLs the summary keeps
+const TOP_ENTRIES: usize = 10;
+
+/// Counters for one run, shown live on stderr and summed up when the run ends.
+pub struct RunStats {
+    // Draw the progress display while a run is going
+    progress: bool,
+    run: Mutex<Option<Run>>,
+}
+
+struct Run {
+    started_at: DateTime<Local>,
+    started: Instant,
+    // Pages the crawler may still fetch; enables the ETA
+    budget: Option<usize>,
+    bar: ProgressBar,
+    pages: usize,
+    queued: usize,
+    requests: usize,
+    errors: usize,
+    bytes: u64,
+    items: usize,
+    status_codes: BTreeMap<u16, usize>,
+    error_causes: HashMap<String, usize>,
+    // Slowest first, at most TOP_ENTRIES
+    slowest: Vec<SlowUrl>,
+}
+
+#[derive(Serialize, Clone)]
+struct SlowUrl {
+    url: String,
+    status: u16,
+    elapsed_ms: u64,
+}
+
+#[derive(Serialize)]
+struct ErrorCause {
+    cause: String,
+    count: usize,
+}
+
+/// What the stats file holds.
+#[derive(Serialize)]
+struct Summary {
+    started_at: String,
+    duration_secs: f64,
+    pages: usize,
+    requests: usize,
+    requests_per_second: f64,
+    errors: usize,
+    bytes_downloaded: u64,
+    items_extracted: usize,
+    status_codes: BTreeMap<u16, usize>,
+    top_errors: Vec<ErrorCause>,
+    slowest_urls: Vec<SlowUrl>,
+}
+
+impl RunStats {
+    pub fn new(progress: bool) -> RunStats {
+        RunStats {
+            progress,
+            run: Mutex::new(None),
+        }
+    }
+
+    /// Starts counting a new run, with a page budget if the crawl has one.
+    pub fn start(&self, budget: Option<usize>) {
+        let bar = if self.progress {
+            ProgressBar::with_draw_target(
+                budget.map(|budget| budget as u64),
+                ProgressDrawTarget::stderr(),
+            )
+        } else {
+            ProgressBar::hidden()
+        };
+        let template = match budget {
+            Some(_) => "{spinner} [{bar:30}] {pos}/{len} pages, {msg}, ETA {eta}",
+            None => "{spinner} {pos} pages, {msg}",
+        };
+        bar.set_style(
+            ProgressStyle::with_template(template)
+                .unwrap()
+                .progress_chars("=> "),
+        );
+        if self.progress {
+            bar.enable_steady_tick(Duration::from_millis(200));
+            console::set_progress_bar(Some(bar.clone()));
+        }
+
+        let run = Run {
+            started_at: Local::now(),
+            started: Instant::now(),
+            budget,
+            bar,
+            pages: 0,
+            queued: 0,
+            requests: 0,
+            errors: 0,
+            bytes: 0,
+            items: 0,
+            status_codes: BTreeMap::new(),
+            error_causes: HashMap::new(),
+            slowest: Vec::new(),
+        };
+        run.update_message();
+        *self.run.lock().unwrap() = Some(run);
+    }
+
+    /// A page was requested, whatever came of it.
+    pub fn record_page(&self) {
+        self.update(|run| {
+            run.pages += 1;
+            run.bar.inc(1);
+        });
+    }
+
+    pub fn record_response(&self, url: &str, status: u16, bytes: u64, elapsed: Duration) {
+        self.update(|run| {
+            run.requests += 1;
+            run.bytes += bytes;
+            *run.status_codes.entry(status).or_default() += 1;
+            if status >= 400 {
+                run.errors += 1;
+                *run.error_causes
+                    .entry(format!("HTTP {}", status))
+                    .or_default() += 1;
+            }
+
+            let elapsed_ms = elapsed.as_millis() as u64;
+            if run.slowest.len() < TOP_ENTRIES
+                || run
+                    .slowest
+                    .last()
+                    .is_some_and(|slow| slow.elapsed_ms < elapsed_ms)
+            {
+                let index = run
+                    .slowest
+                    .partition_point(|slow| slow.elapsed_ms >= elapsed_ms);
+                run.slowest.insert(
+                    index,
+                    SlowUrl {
+                        url: url.to_string(),
+                        status,
+                        elapsed_ms,
+                    },
+                );
+                run.slowest.truncate(TOP_ENTRIES);
+            }
+        });
+    }
+
+    /// A request that got no response, or whose response was rejected.
+    pub fn record_error(&self, err: &(dyn std::error::Error + 'static)) {
+        self.update(|run| {
+            run.requests += 1;
+            run.errors += 1;
+            *run.error_causes.entry(error_cause(err)).or_default() += 1;
+        });
+    }
+
+    /// Body bytes read outside of `record_response`.
+    pub fn record_bytes(&self, bytes: u64) {
+        self.update(|run| run.bytes += bytes);
+    }
+
+    pub fn record_item(&self) {
+        self.update(|run| run.items += 1);
+    }
+
+    pub fn set_queued(&self, queued: usize) {
+        self.update(|run| run.queued = queued);
+    }
+
+    /// Ends the run: clears the progress display, prints the summary table
+    /// unless `quiet`, and saves it to `file_path`.
+    pub fn finish(&self, quiet: bool, file_path: &str) -> std::io::Result<()> {
+        let Some(run) = self.run.lock().unwrap().take() else {
+            return Ok(());
+        };
+        run.bar.finish_and_clear();
+        console::set_progress_bar(None);
+
+        let summary = run.summary();
+        if !quiet {
+            eprintln!("{}", summary.table(run.budget));
+        }
+
+        if let Some(folder) = Path::new(file_path).parent() {
+            fs::create_dir_all(folder)?;
+        }
+        fs::write(file_path, serde_json::to_string_pretty(&summary)?)?;
+        info!(file = %file_path, "Run statistics saved");
+        Ok(())
+    }
+
+    fn update(&self, change: impl FnOnce(&mut Run)) {
+        if let Some(run) = self.run.lock().unwrap().as_mut() {
+            change(run);
+            run.update_message();
+        }
+    }
+}
+
+impl Run {
+    fn requests_per_second(&self) -> f64 {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        if elapsed > 0.0 {
+            self.requests as f64 / elapsed
+        } else {
+            0.0
+        }
+    }
+
+    fn update_message(&self) {
+        self.bar.set_message(format!(
+            "{} queued, {:.1} req/s, {} errors, {}, {} items",
+            self.queued,
+            self.requests_per_second(),
+            self.errors,
+            HumanBytes(self.bytes),
+            self.items
+        ));
+    }
+
+    fn summary(&self) -> Summary {
+        let mut top_errors: Vec<ErrorCause> = self
+            .error_causes
+            .iter()
+            .map(|(cause, &count)| ErrorCause {
+                cause: cause.clone(),
+                count,
+            })
+            .collect();
+        top_errors.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.cause.cmp(&b.cause)));
+        top_errors.truncate(TOP_ENTRIES);
+
+        Summary {
+            started_at: self.started_at.to_rfc3339(),
+            duration_secs: self.started.elapsed().as_secs_f64(),
+            pages: self.pages,
+            requests: self.requests,
+            requests_per_second: self.requests_per_second(),
+            errors: self.errors,
+            bytes_downloaded: self.bytes,
+            items_extracted: self.items,
+            status_codes: self.status_codes.clone(),
+            top_errors,
+            slowest_urls: self.slowest.clone(),
+        }
+    }
+}
+
+impl Summary {
+    fn table(&self, budget: Option<usize>) -> String {
+        let pages = match budget {
+            Some(budget) => format!("{} of {}", self.pages, budget),
+            None => self.pages.to_string(),
+        };
+        let mut lines = vec![
+            "Run summary".to_string(),
+            format!("  {:<18}{:.1}s", "Duration", self.duration_secs),
+            format!("  {:<18}{}", "Pages", pages),
+            format!(
+                "  {:<18}{} ({:.1}/s)",
+                "Requests", self.requests, self.requests_per_second
+            ),
+            format!("  {:<18}{}", "Errors", self.errors),
+            format!(
+                "  {:<18}{}",
+                "Downloaded",
+                HumanBytes(self.bytes_downloaded)
+            ),
+            format!("  {:<18}{}", "Items extracted", self.items_extracted),
+        ];
+
+        if !self.status_codes.is_empty() {
+            lines.push("Status codes".to_string());
+            for (status, count) in &self.status_codes {
+                lines.push(format!("  {:<18}{}", status, count));
+            }
+        }
+        if !self.top_errors.is_empty() {
+            lines.push("Top errors".to_string());
+            for error in &self.top_errors {
+                lines.push(format!("  {:<8}{}", error.count, error.cause));
+            }
+        }
+        if !self.slowest_urls.is_empty() {
+            lines.push("Slowest URLs".to_string());
+            for slow in &self.slowest_urls {
+                lines.push(format!(
+                    "  {:<8}{} {}",
+                    format!("{}ms", slow.elapsed_ms),
+                    slow.status,
+                    slow.url
+                ));
+            }
+        }
+        lines.join("\n")
+    }
+}
+
+/// What went wrong, without the URL, so the same failure on different pages
+/// is counted together: reqwest errors by kind and their innermost cause.
+fn error_cause(err: &(dyn std::error::Error + 'static)) -> String {
+    let Some(request_error) = err.downcast_ref::<reqwest::Error>() else {
+        return err.to_string();
+    };
+    let kind = if request_error.is_timeout() {
+        "timeout"
+    } else if request_error.is_connect() {
+        "connect"
+    } else if request_error.is_redirect() {
+        "redirect"
+    } else if request_error.is_body() || request_error.is_decode() {
+        "body"
+    } else if request_error.is_builder() {
+        "request"
+    } else {
+        "request failed"
+    };
+
+    let mut innermost = None;
+    let mut source = err.source();
+    while let Some(cause) = source {
+        innermost = Some(cause);
+        source = cause.source();
+    }
+    match innermost {
+        Some(cause) => format!("{}: {}", kind, cause),
+        None => kind.to_string(),
+    }
+}
@@ -0,0 +1,181 @@
+use chrono::prelude::*;
+use rusqlite::{params, Connection};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Mutex,
+    },
+};
+
+use crate::{fetch::FetchedPage, graph::LinkEdge};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    base_url TEXT NOT NULL,
+    started_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS pages (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    url TEXT NOT NULL,
+    final_url TEXT NOT NULL,
+    status INTEGER NOT NULL,
+    content_type TEXT NOT NULL,
+    encoding TEXT,
+    encoding_source TEXT,
+    headers TEXT NOT NULL,
+    fetched_at TEXT NOT NULL,
+    elapsed_ms INTEGER NOT NULL,
+    content_hash TEXT NOT NULL,
+    depth INTEGER NOT NULL,
+    referrer TEXT
+);
+CREATE INDEX IF NOT EXISTS pages_url ON pages(url);
+CREATE TABLE IF NOT EXISTS links (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    source_url TEXT NOT NULL,
+    target_url TEXT NOT NULL,
+    anchor_text TEXT NOT NULL,
+    rel TEXT
+);
+CREATE INDEX IF NOT EXISTS links_source ON links(source_url);
+CREATE INDEX IF NOT EXISTS links_target ON links(target_url);
+CREATE TABLE IF NOT EXISTS items (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    page_url TEXT NOT NULL,
+    data TEXT NOT NULL,
+    extracted_at TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS items_page ON items(page_url);
+";
+
+/// SQLite file holding every fetched page, discovered link and extracted item,
+/// grouped by run. Later runs append, so the history can be queried for changes.
+pub struct CrawlDb {
+    connection: Mutex<Connection>,
+    run_id: AtomicI64,
+}
+
+impl CrawlDb {
+    pub fn open(path: &str) -> io::Result<CrawlDb> {
+        let connection = Connection::open(path).map_err(io::Error::other)?;
+        connection.execute_batch(SCHEMA).map_err(io::Error::other)?;
+
+        Ok(CrawlDb {
+            connection: Mutex::new(connection),
+            run_id: AtomicI64::new(0),
+        })
+    }
+
+    /// Starts a new run; everything recorded afterwards belongs to it.
+    pub fn start_run(&self, base_url: &str) -> io::Result<()> {
+        let connection = self.connection.lock().unwrap();
+        connection
+            .execute(
+                "INSERT INTO runs (base_url, started_at) VALUES (?1, ?2)",
+                params![base_url, Local::now().to_rfc3339()],
+            )
+            .map_err(io::Error::other)?;
+        self.run_id
+            .store(connection.last_insert_rowid(), Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn record_page(
+        &self,
+        page: &FetchedPage,
+        depth: usize,
+        referrer: Option<&str>,
+    ) -> io::Result<()> {
+        let headers: Map<String, Value> = page
+            .headers
+            .iter()
+            .map(|(name, value)| {
+                (
+                    name.to_string(),
+                    Value::String(String::from_utf8_lossy(value.as_bytes()).into_owned()),
+                )
+            })
+            .collect();
+
+        self.connection
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT INTO pages (run_id, url, final_url, status, content_type, encoding,
+                                    encoding_source, headers, fetched_at, elapsed_ms, content_hash,
+                                    depth, referrer)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+                params![
+                    self.run_id(),
+                    page.url,
+                    page.final_url,
+                    page.status,
+                    page.mime,
+                    page.charset.map(|(encoding, _)| encoding.name()),
+                    page.charset.map(|(_, source)| source.as_str()),
+                    Value::Object(headers).to_string(),
+                    page.fetched_at.to_rfc3339(),
+                    page.elapsed.as_millis() as i64,
+                    content_hash(&page.bytes),
+                    depth as i64,
+                    referrer,
+                ],
+            )
+            .map_err(io::Error::other)?;
+        Ok(())
+    }
+
+    pub fn record_link(&self, link: &LinkEdge) -> io::Result<()> {
+        self.connection
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT INTO links (run_id, source_url, target_url, anchor_text, rel)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    self.run_id(),
+                    link.source,
+                    link.target,
+                    link.anchor_text,
+                    link.rel
+                ],
+            )
+            .map_err(io::Error::other)?;
+        Ok(())
+    }
+
+    pub fn record_item(&self, page_url: &str, item: &Value) -> io::Result<()> {
+        self.connection
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT INTO items (run_id, page_url, data, extracted_at) VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    self.run_id(),
+                    page_url,
+                    item.to_string(),
+                    Local::now().to_rfc3339()
+                ],
+            )
+            .map_err(io::Error::other)?;
+        Ok(())
+    }
+
+    fn run_id(&self) -> i64 {
+        self.run_id.load(Ordering::Relaxed)
+    }
+}
+
+pub fn content_hash(body: &[u8]) -> String {
+    Sha256::digest(body)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
@@ -0,0 +1,331 @@
+use scraper::{ElementRef, Html, Selector};
+use serde_json::{Map, Value};
+
+/// A `<table>` flattened into header-keyed rows.
+pub struct Table {
+    pub headers: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn row_objects(&self) -> Vec<Map<String, Value>> {
+        self.rows
+            .iter()
+            .map(|row| {
+                self.headers
+                    .iter()
+                    .cloned()
+                    .zip(row.iter().map(|cell| Value::String(cell.clone())))
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+struct Cell {
+    text: String,
+    is_header: bool,
+}
+
+struct GridRow {
+    cells: Vec<Cell>,
+    in_thead: bool,
+}
+
+pub struct TableExtractor;
+
+impl TableExtractor {
+    pub fn new() -> Self {
+        TableExtractor
+    }
+
+    pub fn extract_tables(&self, html: &str, selector: &str) -> Result<Vec<Table>, String> {
+        let table_selector = Selector::parse(selector)
+            .map_err(|err| format!("Invalid table selector '{}': {:?}", selector, err))?;
+        let document = Html::parse_document(html);
+
+        Ok(document
+            .select(&table_selector)
+            .filter(|element| element.value().name() == "table")
+            .map(|table| self.extract_table(table))
+            .filter(|table| !table.rows.is_empty() || !table.headers.is_empty())
+            .collect())
+    }
+
+    fn extract_table(&self, table: ElementRef) -> Table {
+        let grid = build_grid(table);
+        let width = grid.iter().map(|row| row.cells.len()).max().unwrap_or(0);
+
+        // Header rows are the <thead> rows, or failing that the leading rows made only of <th>
+        let has_thead = grid.iter().any(|row| row.in_thead);
+        let header_count = if has_thead {
+            grid.iter().take_while(|row| row.in_thead).count()
+        } else {
+            grid.iter()
+                .take_while(|row| {
+                    !row.cells.is_empty() && row.cells.iter().all(|cell| cell.is_header)
+                })
+                .count()
+        };
+
+        let headers = unique_headers(
+            (0..width)
+                .map(|column| {
+                    // Multi-row headers are joined top to bottom, skipping repeats from rowspans
+                    let mut parts: Vec<&str> = Vec::new();
+                    for row in &grid[..header_count] {
+                        if let Some(cell) = row.cells.get(column) {
+                            if !cell.text.is_empty() && parts.last() != Some(&cell.text.as_str()) {
+                                parts.push(&cell.text);
+                            }
+                        }
+                    }
+                    if parts.is_empty() {
+                        format!("column_{}", column + 1)
+                    } else {
+                        parts.join(" / ")
+                    }
+                })
+                .collect(),
+        );
+
+        let rows = grid[header_count..]
+            .iter()
+            .filter(|row| row.cells.iter().any(|cell| !cell.text.is_empty()))
+            .map(|row| {
+                (0..width)
+                    .map(|column| {
+                        row.cells
+                            .get(column)
+                            .map(|cell| cell.text.clone())
+                            .unwrap_or_default()
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Table { headers, rows }
+    }
+}
+
+/// Lays the table out as a rectangular grid, copying cells into every slot
+/// their `rowspan`/`colspan` covers.
+fn build_grid(table: ElementRef) -> Vec<GridRow> {
+    let mut grid: Vec<GridRow> = Vec::new();
+    // Per column: rows still to be covered by a rowspan, and the spanning cell's content
+    let mut pending: Vec<Option<(usize, String, bool)>> = Vec::new();
+
+    for (row, in_thead) in table_rows(table) {
+        let mut cells: Vec<Cell> = Vec::new();
+        let mut column = 0;
+
+        for cell in row
+            .children()
+            .filter_map(ElementRef::wrap)
+            .filter(|cell| matches!(cell.value().name(), "td" | "th"))
+        {
+            fill_pending(&mut pending, &mut cells, &mut column);
+
+            let text = cell_text(cell);
+            let is_header = cell.value().name() == "th";
+            let colspan = span_attr(cell, "colspan");
+            let rowspan = span_attr(cell, "rowspan");
+
+            for _ in 0..colspan {
+                if rowspan > 1 {
+                    if pending.len() <= column {
+                        pending.resize(column + 1, None);
+                    }
+                    pending[column] = Some((rowspan - 1, text.clone(), is_header));
+                }
+                cells.push(Cell {
+                    text: text.clone(),
+                    is_header,
+                });
+                column += 1;
+            }
+        }
+        // Rowspans reaching past the last cell of this row, padding any gap before them
+        fill_pending(&mut pending, &mut cells, &mut column);
+        if let Some(last) = pending.iter().rposition(Option::is_some) {
+            while column <= last {
+                if pending[column].is_none() {
+                    cells.push(Cell {
+                        text: String::new(),
+                        is_header: false,
+                    });
+                    column += 1;
+                }
+                fill_pending(&mut pending, &mut cells, &mut column);
+            }
+        }
+
+        grid.push(GridRow { cells, in_thead });
+    }
+
+    grid
+}
+
+/// Copies rowspanned cells from earlier rows into the slots starting at `column`.
+fn fill_pending(
+    pending: &mut [Option<(usize, String, bool)>],
+    cells: &mut Vec<Cell>,
+    column: &mut usize,
+) {
+    while let Some(Some((remaining, text, is_header))) = pending.get_mut(*column) {
+        cells.push(Cell {
+            text: text.clone(),
+            is_header: *is_header,
+        });
+        *remaining -= 1;
+        if *remaining == 0 {
+            pending[*column] = None;
+        }
+        *column += 1;
+    }
+}
+
+/// Rows that belong to this table (not to tables nested inside it), in order,
+/// with whether they sit in the `<thead>`.
+fn table_rows(table: ElementRef) -> Vec<(ElementRef, bool)> {
+    let mut rows = Vec::new();
+
+    for child in table.children().filter_map(ElementRef::wrap) {
+        match child.value().name() {
+            "tr" => rows.push((child, false)),
+            "thead" | "tbody" | "tfoot" => {
+                let in_thead = child.value().name() == "thead";
+                rows.extend(
+                    child
+                        .children()
+                        .filter_map(ElementRef::wrap)
+                        .filter(|row| row.value().name() == "tr")
+                        .map(|row| (row, in_thead)),
+                );
+            }
+            _ => {}
+        }
+    }
+
+    // Keep <thead> rows first even when the markup places them elsewhere
+    rows.sort_by_key(|(_, in_thead)| !in_thead);
+    rows
+}
+
+fn span_attr(cell: ElementRef, name: &str) -> usize {
+    cell.value()
+        .attr(name)
+        .and_then(|value| value.trim().parse::<usize>().ok())
+        .filter(|span| *span > 0)
+        .unwrap_or(1)
+        .min(1000)
+}
+
+fn cell_text(cell: ElementRef) -> String {
+    cell.text()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Suffixes repeated header names so every column keeps its own key.
+fn unique_headers(headers: Vec<String>) -> Vec<String> {
+    let mut seen: Vec<String> = Vec::new();
+
+    headers
+        .into_iter()
+        .map(|header| {
+            let mut name = header.clone();
+            let mut suffix = 2;
+            while seen.contains(&name) {
+                name = format!("{}_{}", header, suffix);
+                suffix += 1;
+            }
+            seen.push(name.clone());
+            name
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extract(html: &str) -> Vec<Table> {
+        TableExtractor::new().extract_tables(html, "table").unwrap()
+    }
+
+    #[test]
+    fn keys_rows_by_thead_headers() {
+        let tables = extract(
+            "<table><thead><tr><th>Name</th><th>Price</th></tr></thead>
+             <tbody><tr><td>Tea</td><td>3</td></tr></tbody></table>",
+        );
+        assert_eq!(tables.len(), 1);
+        assert_eq!(tables[0].headers, ["Name", "Price"]);
+        assert_eq!(tables[0].rows, [["Tea", "3"]]);
+        assert_eq!(tables[0].row_objects()[0]["Price"], "3");
+    }
+
+    #[test]
+    fn copies_rowspan_and_colspan_cells() {
+        let tables = extract(
+            "<table>
+               <tr><th>Region</th><th>Q1</th><th>Q2</th></tr>
+               <tr><td rowspan=2>North</td><td colspan=2>10</td></tr>
+               <tr><td>4</td><td>5</td></tr>
+             </table>",
+        );
+        assert_eq!(tables[0].rows, [["North", "10", "10"], ["North", "4", "5"]]);
+    }
+
+    #[test]
+    fn pads_rowspans_past_the_end_of_a_row() {
+        let tables = extract(
+            "<table>
+               <tr><th>A</th><th>B</th><th>C</th></tr>
+               <tr><td>1</td><td>2</td><td rowspan=2>3</td></tr>
+               <tr><td>4</td></tr>
+             </table>",
+        );
+        assert_eq!(tables[0].rows, [["1", "2", "3"], ["4", "", "3"]]);
+    }
+
+    #[test]
+    fn joins_multi_row_headers_without_rowspan_repeats() {
+        let tables = extract(
+            "<table><thead>
+               <tr><th rowspan=2>Name</th><th colspan=2>Price</th></tr>
+               <tr><th>Net</th><th>Gross</th></tr>
+             </thead>
+             <tr><td>Tea</td><td>3</td><td>3.6</td></tr></table>",
+        );
+        assert_eq!(tables[0].headers, ["Name", "Price / Net", "Price / Gross"]);
+    }
+
+    #[test]
+    fn names_missing_and_repeated_headers() {
+        let tables = extract(
+            "<table><tr><th>X</th><th></th><th>X</th></tr><tr><td>1</td><td>2</td><td>3</td></tr></table>",
+        );
+        assert_eq!(tables[0].headers, ["X", "column_2", "X_2"]);
+    }
+
+    #[test]
+    fn nested_table_rows_stay_in_their_own_table() {
+        let tables = extract(
+            "<table id=outer><tr><th>A</th></tr>
+               <tr><td><table><tr><td>x</td></tr><tr><td>y</td></tr></table></td></tr>
+               <tr><td> </td></tr></table>",
+        );
+        let outer = &tables[0];
+        assert_eq!(outer.headers, ["A"]);
+        // The inner rows are not outer rows; their text is part of the cell
+        // that holds the inner table, and the blank row is skipped
+        assert_eq!(outer.rows, [["x y"]]);
+        let inner = &tables[1];
+        assert_eq!(inner.rows, [["x"], ["y"]]);
+    }
+}
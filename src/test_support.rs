@@ -0,0 +1,101 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+
+/// A path in the temp directory that no other test uses.
+pub fn temp_path(name: &str) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("infinite_scraper_{}_{}", std::process::id(), name));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+/// A local HTTP server, or HTTPS with a TLS acceptor, that answers with the
+/// given statuses in turn and then 200, always with the body "ok", and keeps
+/// every request it gets.
+pub struct TestServer {
+    pub addr: SocketAddr,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl TestServer {
+    pub async fn start(tls: Option<TlsAcceptor>, statuses: Vec<u16>) -> TestServer {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let statuses = Arc::new(Mutex::new(VecDeque::from(statuses)));
+
+        let seen = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let (tls, seen, statuses) = (tls.clone(), seen.clone(), statuses.clone());
+                tokio::spawn(async move {
+                    match tls {
+                        // Handshakes the client gives up on are part of the TLS tests
+                        Some(acceptor) => {
+                            if let Ok(stream) = acceptor.accept(stream).await {
+                                respond(stream, &seen, &statuses).await;
+                            }
+                        }
+                        None => respond(stream, &seen, &statuses).await,
+                    }
+                });
+            }
+        });
+
+        TestServer { addr, requests }
+    }
+
+    /// The requests received so far, head and body as text.
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+async fn respond<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    seen: &Mutex<Vec<String>>,
+    statuses: &Mutex<VecDeque<u16>>,
+) {
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 4096];
+    // Read the head, then as much body as Content-Length says
+    loop {
+        let read = match stream.read(&mut buffer).await {
+            Ok(0) | Err(_) => return,
+            Ok(read) => read,
+        };
+        request.extend_from_slice(&buffer[..read]);
+        let text = String::from_utf8_lossy(&request);
+        if let Some(end) = text.find("\r\n\r\n") {
+            let length = text
+                .lines()
+                .find_map(|line| {
+                    line.to_ascii_lowercase()
+                        .strip_prefix("content-length:")
+                        .map(|value| value.trim().parse::<usize>().unwrap())
+                })
+                .unwrap_or(0);
+            if request.len() >= end + 4 + length {
+                break;
+            }
+        }
+    }
+
+    seen.lock()
+        .unwrap()
+        .push(String::from_utf8_lossy(&request).to_string());
+    let status = statuses.lock().unwrap().pop_front().unwrap_or(200);
+    let response = format!(
+        "HTTP/1.1 {} Status\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok",
+        status
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
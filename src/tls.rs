@@ -0,0 +1,336 @@
+use reqwest::tls::{Certificate, Identity, Version};
+use serde::Deserialize;
+use std::fs;
+
+/// Certificates and protocol limits for TLS connections, set with the
+/// command line options or per host in the config file.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct TlsSettings {
+    // PEM files with extra root certificates, trusted next to the built-in ones
+    #[serde(default)]
+    pub ca_certs: Vec<String>,
+    // PEM certificate (chain) and private key for mutual TLS
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+    // "1.2" or "1.3"
+    pub min_version: Option<String>,
+    // Skip certificate verification altogether; a host's false turns a
+    // global --insecure off for it
+    pub insecure: Option<bool>,
+}
+
+impl TlsSettings {
+    /// These settings with a host's overrides on top: its CA certificates
+    /// are added, and anything else it sets replaces the global value.
+    pub fn with_overrides(&self, host: &TlsSettings) -> TlsSettings {
+        TlsSettings {
+            ca_certs: self
+                .ca_certs
+                .iter()
+                .chain(&host.ca_certs)
+                .cloned()
+                .collect(),
+            client_cert: host
+                .client_cert
+                .clone()
+                .or_else(|| self.client_cert.clone()),
+            client_key: host.client_key.clone().or_else(|| self.client_key.clone()),
+            min_version: host
+                .min_version
+                .clone()
+                .or_else(|| self.min_version.clone()),
+            insecure: host.insecure.or(self.insecure),
+        }
+    }
+
+    pub fn is_insecure(&self) -> bool {
+        self.insecure.unwrap_or(false)
+    }
+
+    /// Checks what can be checked without reading any files.
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(version) = &self.min_version {
+            parse_version(version)?;
+        }
+        match (&self.client_cert, &self.client_key) {
+            (Some(_), None) | (None, Some(_)) => {
+                Err("A client certificate and key must be given together".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Adds the certificates, client identity and version limit to a client
+    /// builder, reading the PEM files.
+    pub fn apply(
+        &self,
+        mut builder: reqwest::ClientBuilder,
+    ) -> Result<reqwest::ClientBuilder, String> {
+        for path in &self.ca_certs {
+            let pem = read(path)?;
+            let certs = Certificate::from_pem_bundle(&pem)
+                .map_err(|err| format!("Invalid CA certificate {}: {}", path, err))?;
+            if certs.is_empty() {
+                return Err(format!("No certificates found in {}", path));
+            }
+            for cert in certs {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+
+        if let (Some(cert), Some(key)) = (&self.client_cert, &self.client_key) {
+            let mut pem = read(cert)?;
+            pem.push(b'\n');
+            pem.extend(read(key)?);
+            let identity = Identity::from_pem(&pem).map_err(|err| {
+                format!(
+                    "Invalid client certificate {} or key {}: {}",
+                    cert, key, err
+                )
+            })?;
+            builder = builder.identity(identity);
+        }
+
+        if let Some(version) = &self.min_version {
+            builder = builder.min_tls_version(parse_version(version)?);
+        }
+        if self.is_insecure() {
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+        Ok(builder)
+    }
+}
+
+fn parse_version(version: &str) -> Result<Version, String> {
+    match version {
+        "1.2" => Ok(Version::TLS_1_2),
+        "1.3" => Ok(Version::TLS_1_3),
+        _ => Err(format!(
+            "Unsupported minimum TLS version '{}': expected 1.2 or 1.3",
+            version
+        )),
+    }
+}
+
+fn read(path: &str) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|err| format!("Error reading {}: {}", path, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{temp_path, TestServer};
+    use rcgen::{BasicConstraints, CertificateParams, CertifiedIssuer, IsCa, KeyPair};
+    use std::{net::SocketAddr, sync::Arc};
+    use tokio_rustls::{
+        rustls::{
+            self,
+            pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer},
+            server::WebPkiClientVerifier,
+            RootCertStore, ServerConfig,
+        },
+        TlsAcceptor,
+    };
+
+    /// A CA with a certificate for localhost and a client certificate, and
+    /// their PEM files.
+    struct Pki {
+        ca_pem: String,
+        ca_der: CertificateDer<'static>,
+        server_der: CertificateDer<'static>,
+        server_key: Vec<u8>,
+        client_cert: String,
+        client_key: String,
+    }
+
+    impl Drop for Pki {
+        fn drop(&mut self) {
+            for path in [&self.ca_pem, &self.client_cert, &self.client_key] {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+
+    fn pki(name: &str) -> Pki {
+        let mut params = CertificateParams::new(Vec::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = CertifiedIssuer::self_signed(params, KeyPair::generate().unwrap()).unwrap();
+
+        let server_key = KeyPair::generate().unwrap();
+        let server = CertificateParams::new(vec!["localhost".to_string()])
+            .unwrap()
+            .signed_by(&server_key, &ca)
+            .unwrap();
+        let client_key = KeyPair::generate().unwrap();
+        let client = CertificateParams::new(vec!["scraper".to_string()])
+            .unwrap()
+            .signed_by(&client_key, &ca)
+            .unwrap();
+
+        Pki {
+            ca_pem: write_temp(&format!("{}_ca.pem", name), &ca.pem()),
+            ca_der: ca.der().clone(),
+            server_der: server.der().clone(),
+            server_key: server_key.serialize_der(),
+            client_cert: write_temp(&format!("{}_client.pem", name), &client.pem()),
+            client_key: write_temp(
+                &format!("{}_client_key.pem", name),
+                &client_key.serialize_pem(),
+            ),
+        }
+    }
+
+    fn write_temp(name: &str, contents: &str) -> String {
+        let path = temp_path(name);
+        fs::write(&path, contents).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    /// An HTTPS server for localhost answering every request with "ok",
+    /// optionally only over TLS 1.2 or only to clients with a certificate
+    /// from the CA.
+    async fn serve(pki: &Pki, tls12_only: bool, client_auth: bool) -> SocketAddr {
+        let provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+        let versions: &[&rustls::SupportedProtocolVersion] = if tls12_only {
+            &[&rustls::version::TLS12]
+        } else {
+            &[&rustls::version::TLS12, &rustls::version::TLS13]
+        };
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_protocol_versions(versions)
+            .unwrap();
+        let builder = if client_auth {
+            let mut roots = RootCertStore::empty();
+            roots.add(pki.ca_der.clone()).unwrap();
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .unwrap();
+            builder.with_client_cert_verifier(verifier)
+        } else {
+            builder.with_no_client_auth()
+        };
+        let config = builder
+            .with_single_cert(
+                vec![pki.server_der.clone()],
+                PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(pki.server_key.clone())),
+            )
+            .unwrap();
+
+        TestServer::start(Some(TlsAcceptor::from(Arc::new(config))), Vec::new())
+            .await
+            .addr
+    }
+
+    async fn get(tls: &TlsSettings, addr: SocketAddr) -> Result<String, String> {
+        let client = tls
+            .apply(reqwest::Client::builder().resolve("localhost", addr))?
+            .build()
+            .map_err(|err| err.to_string())?;
+        let response = client
+            .get(format!("https://localhost:{}/", addr.port()))
+            .send()
+            .await
+            .map_err(|err| err.to_string())?;
+        response.text().await.map_err(|err| err.to_string())
+    }
+
+    #[tokio::test]
+    async fn trusts_the_ca_bundle() {
+        let pki = pki("ca_bundle");
+        let addr = serve(&pki, false, false).await;
+
+        assert!(get(&TlsSettings::default(), addr).await.is_err());
+        let tls = TlsSettings {
+            ca_certs: vec![pki.ca_pem.clone()],
+            ..Default::default()
+        };
+        assert_eq!(get(&tls, addr).await, Ok("ok".to_string()));
+    }
+
+    #[tokio::test]
+    async fn insecure_skips_verification() {
+        let pki = pki("insecure");
+        let addr = serve(&pki, false, false).await;
+
+        let tls = TlsSettings {
+            insecure: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(get(&tls, addr).await, Ok("ok".to_string()));
+        let tls = TlsSettings {
+            insecure: Some(false),
+            ..Default::default()
+        };
+        assert!(get(&tls, addr).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn presents_the_client_certificate() {
+        let pki = pki("client_cert");
+        let addr = serve(&pki, false, true).await;
+
+        let mut tls = TlsSettings {
+            ca_certs: vec![pki.ca_pem.clone()],
+            ..Default::default()
+        };
+        assert!(get(&tls, addr).await.is_err());
+        tls.client_cert = Some(pki.client_cert.clone());
+        tls.client_key = Some(pki.client_key.clone());
+        assert_eq!(get(&tls, addr).await, Ok("ok".to_string()));
+    }
+
+    #[tokio::test]
+    async fn enforces_the_minimum_version() {
+        let pki = pki("min_version");
+        let addr = serve(&pki, true, false).await;
+
+        let mut tls = TlsSettings {
+            ca_certs: vec![pki.ca_pem.clone()],
+            min_version: Some("1.2".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(get(&tls, addr).await, Ok("ok".to_string()));
+        tls.min_version = Some("1.3".to_string());
+        assert!(get(&tls, addr).await.is_err());
+    }
+
+    #[test]
+    fn host_overrides_replace_the_global_settings() {
+        let global = TlsSettings {
+            ca_certs: vec!["global.pem".to_string()],
+            min_version: Some("1.2".to_string()),
+            insecure: Some(true),
+            ..Default::default()
+        };
+        let host = TlsSettings {
+            ca_certs: vec!["host.pem".to_string()],
+            min_version: Some("1.3".to_string()),
+            insecure: Some(false),
+            ..Default::default()
+        };
+
+        let merged = global.with_overrides(&host);
+        assert_eq!(merged.ca_certs, ["global.pem", "host.pem"]);
+        assert_eq!(merged.min_version.as_deref(), Some("1.3"));
+        assert!(!merged.is_insecure());
+        assert!(global.with_overrides(&TlsSettings::default()).is_insecure());
+    }
+
+    #[test]
+    fn validate_checks_versions_and_key_pairs() {
+        let tls = TlsSettings {
+            min_version: Some("1.1".to_string()),
+            ..Default::default()
+        };
+        assert!(tls.validate().is_err());
+        let tls = TlsSettings {
+            client_cert: Some("client.pem".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            tls.validate(),
+            Err("A client certificate and key must be given together".to_string())
+        );
+    }
+}